@@ -1,15 +1,22 @@
 use anyhow::Error;
-use std::io::BufRead;
+use std::io::Read;
 use std::result::Result::Ok;
 
-use crate::pattern_matcher::match_input;
-use std::{fs::File, io};
+use rusty_grep::pattern_matcher::PatternMatcher;
+use rusty_grep::Pattern::MatchError;
+#[cfg(test)]
+use rusty_grep::Pattern::PatternOptions;
+use std::fs::File;
+use std::ops::Range;
+
+// a line is where it is in the file, the bytes don't have to be valid UTF-8
 pub struct Line {
-    content: String,
+    range: Range<usize>,
 }
 
+// the file as it was read, "\r" and the last newline included, and where each line is in it
 pub struct _File {
-    name: String,
+    content: Vec<u8>,
     lines: Vec<Line>,
 }
 
@@ -17,45 +24,135 @@ impl _File {
     pub fn new(file_name: String) -> Result<Self, Error> {
         let file_res = File::open(&file_name);
         match file_res {
-            Ok(file) => {
-                let reader = io::BufReader::new(file);
-
-                let lines_res: io::Result<Vec<Line>> = reader
-                    .lines()
-                    .map(|line_result| line_result.map(|line| Line { content: line }))
-                    .collect();
-                match lines_res {
-                    Ok(lines) => Ok(_File {
-                        name: file_name,
-                        lines,
-                    }),
+            Ok(mut file) => {
+                let mut content = vec![];
+                match file.read_to_end(&mut content) {
+                    Ok(_) => Ok(_File::from_content(content)),
                     _ => Err(Error::msg("failed to read file content")),
                 }
             }
             _ => Err(Error::msg("failed to open that file")),
         }
     }
-    pub fn match_file<'a>(&'a self, pattern: &str) -> Vec<&'a String> {
+
+    fn from_content(content: Vec<u8>) -> Self {
+        let mut lines = vec![];
+        let mut start = 0;
+        while start < content.len() {
+            let end = memchr::memchr(b'\n', &content[start..]).map_or(content.len(), |n| start + n);
+            lines.push(Line::new(&content, start..end));
+            start = end + 1;
+        }
+        _File { content, lines }
+    }
+
+    pub fn match_file<'a>(&'a self, matcher: &PatternMatcher) -> Result<Vec<&'a [u8]>, MatchError> {
         let mut file_lines: Vec<&Line> = Vec::new();
         for line in &self.lines {
-            if line.match_line(pattern) {
+            if line.match_line(&self.content, matcher)? {
                 file_lines.push(line);
             }
         }
-        get_lines_content(&file_lines)
+        Ok(get_lines_content(&self.content, &file_lines))
+    }
+
+    // the whole file is searched at once, so a match can span lines (a\nb, (?s)a.*b);
+    // every line a match covers is kept, or only the text of the matches
+    pub fn match_file_multiline(
+        &self,
+        matcher: &PatternMatcher,
+        only_matching: bool,
+    ) -> Result<Vec<Vec<u8>>, MatchError> {
+        if self.lines.is_empty() {
+            return Ok(vec![]);
+        }
+        // the line an offset is in, the newline ending a line is part of it
+        let line_of = |offset: usize| {
+            self.lines
+                .partition_point(|line| line.range.start <= offset)
+                - 1
+        };
+
+        let mut matched = vec![false; self.lines.len()];
+        let mut texts = vec![];
+        for span in matcher.try_find_spans(&self.content)? {
+            if only_matching {
+                if !span.is_empty() {
+                    texts.push(self.content[span].to_vec());
+                }
+                continue;
+            }
+            // there is no line after the newline that ends the file
+            if span.start == self.content.len() && self.content.ends_with(b"\n") {
+                continue;
+            }
+            // a match ending right after a newline doesn't cover the next line
+            let last = span.end.saturating_sub(1).max(span.start);
+            for covered in &mut matched[line_of(span.start)..=line_of(last)] {
+                *covered = true;
+            }
+        }
+        if only_matching {
+            return Ok(texts);
+        }
+        let lines: Vec<&Line> = self
+            .lines
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| *matched)
+            .map(|(line, _)| line)
+            .collect();
+        Ok(get_lines_content(&self.content, &lines)
+            .into_iter()
+            .map(|line| line.to_vec())
+            .collect())
     }
 }
 
 impl Line {
-    fn match_line(&self, pattern: &str) -> bool {
-        match_input(&self.content, pattern)
+    // like BufRead::lines, a "\r\n" ending isn't part of the line
+    fn new(content: &[u8], mut range: Range<usize>) -> Self {
+        if range.end > range.start && content[range.end - 1] == b'\r' {
+            range.end -= 1;
+        }
+        Line { range }
+    }
+
+    fn match_line(&self, content: &[u8], matcher: &PatternMatcher) -> Result<bool, MatchError> {
+        matcher.try_is_match_bytes(&content[self.range.clone()])
     }
 }
 
-fn get_lines_content<'a>(lines: &[&'a Line]) -> Vec<&'a String> {
+fn get_lines_content<'a>(content: &'a [u8], lines: &[&Line]) -> Vec<&'a [u8]> {
     let mut file_content = vec![];
     for line in lines {
-        file_content.push(&line.content);
+        file_content.push(&content[line.range.clone()]);
     }
     file_content
 }
+
+#[test]
+fn test_multiline_search_sees_the_file_as_it_is() {
+    let matcher = PatternMatcher::new(r"b\r\nc\n").unwrap();
+    let file = _File::from_content(b"a\r\nb\r\nc\n".to_vec());
+    assert_eq!(
+        file.match_file_multiline(&matcher, false).unwrap(),
+        [b"b", b"c"]
+    );
+    assert_eq!(
+        file.match_file_multiline(&matcher, true).unwrap(),
+        [b"b\r\nc\n"]
+    );
+    // the lines are the same as when each line is searched on its own
+    let matcher = PatternMatcher::with_options(
+        "^$",
+        &PatternOptions {
+            multi_line: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let file = _File::from_content(b"a\n\nb\n".to_vec());
+    assert_eq!(file.match_file_multiline(&matcher, false).unwrap(), [b""]);
+    assert_eq!(file.match_file(&matcher).unwrap(), [b""]);
+}
@@ -1,358 +1,1510 @@
-use std::{
-    io::Read,
-    str::{Chars, FromStr},
-    vec,
-};
-
-#[derive(Debug, PartialEq)]
+use std::ops::Range;
+use std::str::{Chars, FromStr};
+
+use crate::captures::{Captures, Match};
+use crate::dfa::LazyDfa;
+pub use crate::error::{MatchError, ParseError, ParseErrorKind};
+use crate::limits::Limits;
+use crate::nfa::{self, Program};
+use crate::optimizer;
+use crate::prefilter::Prefilter;
+use crate::syntax::{self, Syntax};
+use crate::unicode::{self, Property};
+use crate::utf8;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Literal(char),
+    Text(String), // abc, a run of literals merged by the optimizer
+    WildCard,     // .  any char but a newline
+    AnyChar,      // .  under (?s), a newline too
     CharClass(CharClass),
     GroupClass(GroupClass),
-    SOL(Vec<Token>),   // Start Of Line
-    EOL(Vec<Token>),   // End Of Line
-    Exact(Vec<Token>), // ^....$
+    Range(char, char),          // a-z (only inside a GroupClass)
+    Group(Group),               // (abc)  (cat|dog)
+    BackReference(usize),       // \1 .. \9
+    NamedBackReference(String), // \k<name>, only until the parser resolves its number
+    Assertion(Assertion),       // ^ $ \b ... they match a position, not a char
+    LookAround(LookAround),     // (?=...) (?!...) (?<=...) (?<!...), zero-width as well
     OneORMore(Box<Token>),
     OneOrNone(Box<Token>),
+    ZeroOrMore(Box<Token>),
+    Repeat(Box<Token>, usize, Option<usize>), // {min,max}, no max for {n,}
+    Lazy(Box<Token>), // a quantifier that takes as few repetitions as it can  (a+? a*? a?? a{2,5}?)
+    Atomic(Box<Token>), // (?>...) and possessive quantifiers (a++ a*+), never backtracked into
+    SetFlags(Flags),  // (?i) (?-i), they apply until the end of the enclosing group
+    CaseInsensitive(Box<Token>), // a literal, class or backreference under (?i)
 }
 
-#[derive(Debug, PartialEq)]
+// the largest count allowed in {n,m}, bigger counts are rejected by the parser
+pub const MAX_REPEAT: usize = 1000;
+
+// the most chars a lookbehind may match, so the matcher knows how far back to start it
+pub const MAX_LOOK_BEHIND: usize = 255;
+
+// \d \w \s know every script, [:alpha:] and the other POSIX classes are ASCII only
+#[derive(Debug, PartialEq, Clone)]
 pub enum CharClass {
-    Digit,      // \d
-    Identifier, // \w
+    Digit,               // \d  (Unicode decimal numbers)
+    Identifier,          // \w  (Unicode letters, marks, digits and connectors like _)
+    Space,               // \s  (Unicode white space)
+    AsciiDigit,          // [:digit:], \d with PatternOptions::ascii_only
+    AsciiIdentifier,     // \w with PatternOptions::ascii_only
+    AsciiSpace,          // [:space:], \s with PatternOptions::ascii_only
+    Alnum,               // [:alnum:]
+    Alpha,               // [:alpha:]
+    Blank,               // [:blank:]
+    Cntrl,               // [:cntrl:]
+    Graph,               // [:graph:]
+    Lower,               // [:lower:]
+    Print,               // [:print:]
+    Punct,               // [:punct:]
+    Upper,               // [:upper:]
+    XDigit,              // [:xdigit:]
+    Property(Property),  // \p{Greek}  \p{Lu}
+    Not(Box<CharClass>), // \D \W \S \P{..}
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum GroupClass {
     MatchOne(Vec<Token>),
     MatchNone(Vec<Token>),
 }
-#[derive(Debug, PartialEq)]
-pub enum Anchor {
-    Start, // ^abc
-    End,   // abc$
-    Both,  // ^abc$
+
+// a parenthesized group, each alternative of (a|b|c) is parsed into its own SubPattern
+// so nested groups just become nested trees instead of expanded strings
+#[derive(Debug, PartialEq, Clone)]
+pub struct Group {
+    pub alternatives: Vec<SubPattern>,
+    pub capture: Option<CaptureGroup>, // None for (?:...)
 }
 
-#[derive(Debug)]
-pub enum Remaining<'a> {
-    Single(Option<&'a str>),
-    Multiple(Vec<Option<&'a str>>),
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaptureGroup {
+    pub index: usize,         // groups are numbered from 1 in the order of their '('
+    pub name: Option<String>, // (?<name>...)
+}
+
+// checks the input around the current position without consuming it
+#[derive(Debug, PartialEq, Clone)]
+pub struct LookAround {
+    pub alternatives: Vec<SubPattern>,
+    pub direction: LookDirection,
+    pub negated: bool, // (?!...) (?<!...)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LookDirection {
+    Ahead,                     // (?=...) the alternatives start at the position
+    Behind { max_len: usize }, // (?<=...) they end at it, and are at most max_len chars long
+}
+
+// inline flags, only the ones written down are Some:  (?i) → Some(true)  (?-i) → Some(false)
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Flags {
+    pub case_insensitive: Option<bool>,     // i
+    pub dot_matches_new_line: Option<bool>, // s
+    pub multi_line: Option<bool>,           // m
+}
+
+impl Flags {
+    // the flags in effect once `other` is set on top of these
+    fn merge(self, other: Flags) -> Flags {
+        Flags {
+            case_insensitive: other.case_insensitive.or(self.case_insensitive),
+            dot_matches_new_line: other.dot_matches_new_line.or(self.dot_matches_new_line),
+            multi_line: other.multi_line.or(self.multi_line),
+        }
+    }
+}
+
+// zero-width assertions, usable anywhere in the pattern
+#[derive(Debug, PartialEq, Clone)]
+pub enum Assertion {
+    StartOfLine,          // ^
+    EndOfLine,            // $
+    MultiLineStart,       // ^ with (?m): also right after a \n
+    MultiLineEnd,         // $ with (?m): also right before a \n
+    WordBoundary,         // \b
+    NotWordBoundary,      // \B
+    AsciiWordBoundary,    // \b with PatternOptions::ascii_only
+    AsciiNotWordBoundary, // \B with PatternOptions::ascii_only
+    StartOfText,          // \A
+    EndOfText,            // \z
 }
 
 impl Token {
+    // the token lists nested inside this token (one per alternative for a group)
+    pub fn children(&self) -> Vec<&[Token]> {
+        match self {
+            Self::Group(Group { alternatives, .. })
+            | Self::LookAround(LookAround { alternatives, .. }) => alternatives
+                .iter()
+                .map(|alternative| alternative.tokens.as_slice())
+                .collect(),
+            Self::OneORMore(token)
+            | Self::OneOrNone(token)
+            | Self::ZeroOrMore(token)
+            | Self::Repeat(token, ..)
+            | Self::Lazy(token)
+            | Self::Atomic(token)
+            | Self::CaseInsensitive(token) => vec![std::slice::from_ref(token)],
+            _ => vec![],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut [Token]> {
+        match self {
+            Self::Group(Group { alternatives, .. })
+            | Self::LookAround(LookAround { alternatives, .. }) => alternatives
+                .iter_mut()
+                .map(|alternative| alternative.tokens.as_mut_slice())
+                .collect(),
+            Self::OneORMore(token)
+            | Self::OneOrNone(token)
+            | Self::ZeroOrMore(token)
+            | Self::Repeat(token, ..)
+            | Self::Lazy(token)
+            | Self::Atomic(token)
+            | Self::CaseInsensitive(token) => vec![std::slice::from_mut(token.as_mut())],
+            _ => vec![],
+        }
+    }
+
     // ------------------------------------------------------------------------------//
     //                                 Token Matcher                                 //
     // ------------------------------------------------------------------------------//
 
-    pub fn _match<'a>(&self, str: &'a str) -> Option<Remaining<'a>> {
+    // the most chars the token can match, None when there is no limit  (a+  \1)
+    fn max_len(&self) -> Option<usize> {
         match self {
-            Self::Literal(c) if str.chars().next()? == *c || *c == '.' => {
-                // (*c == '.') we add tatht to support wildcard
-                Some(Remaining::Single(Some(skip(str, 1))))
-            }
-            //Self::WildCard => Some(Remaining::Single(Some(skip(str, 1)))),
-            Self::CharClass(char_class)
-                if Self::match_char_class(char_class, str.chars().next()?) =>
-            {
-                Some(Remaining::Single(Some(skip(str, 1))))
+            Self::Literal(_)
+            | Self::WildCard
+            | Self::AnyChar
+            | Self::CharClass(_)
+            | Self::GroupClass(_) => Some(1),
+            Self::Text(text) => Some(text.chars().count()),
+            Self::Range(..) | Self::Assertion(_) | Self::LookAround(_) | Self::SetFlags(_) => {
+                Some(0)
             }
-            Self::SOL(sub_tokens) => Self::match_group(sub_tokens.as_slice(), str, &mut vec![]),
-            Self::EOL(sub_tokens) => Self::match_group(sub_tokens.as_slice(), str, &mut vec![]),
-            Self::Exact(sub_tokens) => {
-                Self::match_excat_group(sub_tokens.as_slice(), str, &mut vec![])
+            Self::BackReference(_) | Self::NamedBackReference(_) => None,
+            Self::Group(group) => Self::alternatives_max_len(&group.alternatives),
+            Self::OneORMore(token) | Self::ZeroOrMore(token) | Self::Repeat(token, _, None) => {
+                match token.max_len()? {
+                    0 => Some(0),
+                    _ => None,
+                }
             }
-            Self::OneORMore(token) => Self::match_one_or_more(token, str),
-            Self::OneOrNone(token) => Self::match_one_or_none(token, str),
+            Self::Repeat(token, _, Some(max)) => token.max_len()?.checked_mul(*max),
+            Self::OneOrNone(token)
+            | Self::Lazy(token)
+            | Self::Atomic(token)
+            | Self::CaseInsensitive(token) => token.max_len(),
+        }
+    }
 
-            _ => None,
+    // whether the token can match without consuming a char  (a?  ^  (|b)  \1)
+    // true when it isn't sure, the Pike VM only uses it to leave out a check
+    pub(crate) fn can_match_empty(&self) -> bool {
+        match self {
+            Self::Literal(_)
+            | Self::WildCard
+            | Self::AnyChar
+            | Self::CharClass(_)
+            | Self::GroupClass(_) => false,
+            Self::Text(text) => text.is_empty(),
+            Self::Range(..)
+            | Self::Assertion(_)
+            | Self::LookAround(_)
+            | Self::SetFlags(_)
+            | Self::BackReference(_)
+            | Self::NamedBackReference(_)
+            | Self::OneOrNone(_)
+            | Self::ZeroOrMore(_) => true,
+            Self::Group(group) => group.alternatives.iter().any(|alternative| {
+                alternative
+                    .tokens
+                    .iter()
+                    .all(|token| token.can_match_empty())
+            }),
+            Self::Repeat(token, min, _) => *min == 0 || token.can_match_empty(),
+            Self::OneORMore(token)
+            | Self::Lazy(token)
+            | Self::Atomic(token)
+            | Self::CaseInsensitive(token) => token.can_match_empty(),
         }
     }
 
-    fn match_char_class(class: &CharClass, c: char) -> bool {
-        match class {
-            CharClass::Digit => c.is_ascii_digit(),
-            CharClass::Identifier => c.is_ascii_alphabetic() || c == '_',
+    fn alternatives_max_len(alternatives: &[SubPattern]) -> Option<usize> {
+        alternatives.iter().try_fold(0, |max_len, alternative| {
+            let len = alternative
+                .tokens
+                .iter()
+                .try_fold(0, |len: usize, token| len.checked_add(token.max_len()?))?;
+            Some(max_len.max(len))
+        })
+    }
+
+    // try to match the token at byte offset `pos` of the input.
+    // `next` is the rest of the pattern: it gets the offset right after what the token consumed
+    // and tells if everything after the token matches from there, so when it fails
+    // the token can try its other options (the other alternatives, one repetition less, ...)
+    pub fn _match(&self, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        // every token tried is a step, and the stack grows until `next` returns
+        if !state.step(1) || !state.backtrack() {
+            return false;
+        }
+        let marker = 0u8;
+        let stack_pos = std::ptr::addr_of!(marker) as usize;
+        if state.depth == 0 {
+            state.stack_base = stack_pos;
         }
+        if state.stack_base.abs_diff(stack_pos) > state.stack_limit {
+            let limit = state.stack_limit;
+            state
+                .error
+                .get_or_insert(MatchError::StackLimitExceeded { limit });
+            return false;
+        }
+        state.depth += 1;
+        let matched = self.match_token(state, pos, next);
+        state.depth -= 1;
+        matched
     }
 
-    fn match_one_or_more<'a>(literal_token: &Box<Token>, s: &'a str) -> Option<Remaining<'a>> {
-        let mut remainings: Vec<Option<&'a str>> = Vec::new();
+    fn match_token(&self, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        match self {
+            Self::Literal(_)
+            | Self::WildCard
+            | Self::AnyChar
+            | Self::CharClass(_)
+            | Self::GroupClass(_) => match state.char_at(pos) {
+                Some((ch, len)) if self.match_char(ch, false) => next(state, pos + len),
+                _ => false,
+            },
+            Self::Text(text) => {
+                state.input[pos..].starts_with(text.as_bytes()) && next(state, pos + text.len())
+            }
+            Self::Range(..) => false,
+            Self::Group(group) => Self::match_group(group, state, pos, next),
+            Self::BackReference(index) => match state.slots[*index] {
+                // the group must have matched already, then the same text has to follow
+                Some((start, end)) => {
+                    let captured = &state.input[start..end];
+                    state.input[pos..].starts_with(captured) && next(state, pos + captured.len())
+                }
+                None => false,
+            },
+            Self::NamedBackReference(_) => false,
+            Self::Assertion(assertion) => {
+                Self::match_assertion(assertion, state, pos) && next(state, pos)
+            }
+            Self::LookAround(look_around) => Self::match_look_around(look_around, state, pos, next),
+            Self::OneORMore(token) => Self::match_one_or_more(token, state, pos, next),
+            Self::OneOrNone(token) => Self::match_one_or_none(token, state, pos, next),
+            Self::ZeroOrMore(token) => {
+                Self::match_one_or_more(token, state, pos, next) || next(state, pos)
+            }
+            Self::Repeat(token, min, max) => {
+                Self::match_repeat(token, *min, *max, 0, state, pos, next)
+            }
+            Self::Lazy(quantifier) => match quantifier.repeat_range() {
+                Some((token, min, max)) => {
+                    Self::match_repeat_lazy(token, min, max, 0, state, pos, next)
+                }
+                None => false,
+            },
+            Self::Atomic(token) => Self::match_atomic(token, state, pos, next),
+            Self::SetFlags(_) => next(state, pos),
+            Self::CaseInsensitive(token) => match token.as_ref() {
+                Self::BackReference(index) => match state.slots[*index] {
+                    Some((start, end)) => match Self::match_ignoring_case(
+                        &state.input[start..end],
+                        &state.input[pos..],
+                    ) {
+                        Some(len) => next(state, pos + len),
+                        None => false,
+                    },
+                    None => false,
+                },
+                Self::Text(text) => {
+                    let mut len = 0;
+                    for c in text.chars() {
+                        match state.char_at(pos + len) {
+                            Some((ch, ch_len)) if Self::Literal(c).match_char(ch, true) => {
+                                len += ch_len
+                            }
+                            _ => return false,
+                        }
+                    }
+                    next(state, pos + len)
+                }
+                token => match state.char_at(pos) {
+                    Some((ch, len)) if token.match_char(ch, true) => next(state, pos + len),
+                    _ => false,
+                },
+            },
+        }
+    }
 
-        // First, try matching the token at least once
-        if let Some(rem) = literal_token._match(s) {
-            match rem {
-                Remaining::Single(remaining_str) => remainings.push(remaining_str),
-                _ => return None,
+    // does the token that stands for one char (a literal, a class, the wildcard) accept `c`
+    // with `ignore_case` any char that is the same letter as `c` is accepted too
+    pub(crate) fn match_char(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            Self::Literal(l) => *l == c || ignore_case && unicode::simple_case_folds(c).contains(l),
+            Self::WildCard => c != '\n',
+            Self::AnyChar => true,
+            Self::CaseInsensitive(token) => token.match_char(c, true),
+            Self::Range(start, end) => {
+                let in_range = |c: &char| (*start..=*end).contains(c);
+                in_range(&c) || ignore_case && unicode::simple_case_folds(c).iter().any(in_range)
             }
-        } else {
-            return None; // doesn't match even one
+            Self::CharClass(class) => Self::match_char_class(class, c, ignore_case),
+            Self::GroupClass(group_class) => Self::match_group_class(group_class, c, ignore_case),
+            _ => false,
         }
+    }
 
-        while let Some(last) = remainings.last().and_then(|opt| *opt) {
-            if let Some(rem) = literal_token._match(last) {
-                match rem {
-                    Remaining::Single(remaining_str) => remainings.push(remaining_str),
-                    _ => break,
+    // the byte length of the start of `input` that is `text` when case is ignored
+    fn match_ignoring_case(text: &[u8], input: &[u8]) -> Option<usize> {
+        let mut input_chars = utf8::chars(input);
+        let mut len = 0;
+        for (c, _) in utf8::chars(text) {
+            match input_chars.next() {
+                Some((ch, ch_len)) if ch == c || unicode::simple_case_folds(c).contains(&ch) => {
+                    len += ch_len
                 }
-            } else {
-                break;
+                _ => return None,
             }
         }
+        Some(len)
+    }
+
+    // the repeated token and its {min,max} when this token is a quantifier
+    pub(crate) fn repeat_range(&self) -> Option<(&Token, usize, Option<usize>)> {
+        match self {
+            Self::OneORMore(token) => Some((token, 1, None)),
+            Self::OneOrNone(token) => Some((token, 0, Some(1))),
+            Self::ZeroOrMore(token) => Some((token, 0, None)),
+            Self::Repeat(token, min, max) => Some((token, *min, *max)),
+            _ => None,
+        }
+    }
 
-        Some(Remaining::Multiple(remainings))
+    // without (?m) ^ and $ are the start and end of what is matched: one line for grep,
+    // the whole text in multiline search
+    pub(crate) fn match_assertion(assertion: &Assertion, state: &MatchState, pos: usize) -> bool {
+        let before = state.char_before(pos).map(|(c, _)| c);
+        Self::assertion_holds(assertion, before, state.char_at(pos).map(|(c, _)| c))
     }
 
-    fn match_one_or_none<'a>(literal_token: &Box<Token>, s: &'a str) -> Option<Remaining<'a>> {
-        let mut remainings: Vec<Option<&'a str>> = Vec::new();
+    // an assertion only looks at the chars on both sides of the position, None at the ends
+    pub(crate) fn assertion_holds(
+        assertion: &Assertion,
+        before: Option<char>,
+        after: Option<char>,
+    ) -> bool {
+        let at_word_boundary = |word: CharClass| {
+            let is_word =
+                |c: Option<char>| c.is_some_and(|c| Self::match_char_class(&word, c, false));
+            is_word(before) != is_word(after)
+        };
+        match assertion {
+            Assertion::StartOfLine | Assertion::StartOfText => before.is_none(),
+            Assertion::EndOfLine | Assertion::EndOfText => after.is_none(),
+            Assertion::MultiLineStart => matches!(before, None | Some('\n')),
+            Assertion::MultiLineEnd => matches!(after, None | Some('\n')),
+            Assertion::WordBoundary => at_word_boundary(CharClass::Identifier),
+            Assertion::NotWordBoundary => !at_word_boundary(CharClass::Identifier),
+            Assertion::AsciiWordBoundary => at_word_boundary(CharClass::AsciiIdentifier),
+            Assertion::AsciiNotWordBoundary => !at_word_boundary(CharClass::AsciiIdentifier),
+        }
+    }
 
-        match literal_token._match(s) {
-            // if the char matc if we consume directly that can lead to problem
-            // if that is the case :   pattern = ca?at   and input = cat
-            // so we will return  vector or remaning strs  and in our case  it will contain 2 str
-            // one if we will consume and one if we won't consue
-            Some(remaning) => {
-                remainings.push(Some(s));
-                if let Remaining::Single(remaining_str) = remaning {
-                    remainings.push(remaining_str);
-                };
-                Some(Remaining::Multiple(remainings))
+    // like an atomic group, the first way the alternatives match is the one we keep
+    // a positive lookaround keeps what its groups captured, a negative one never does
+    fn match_look_around(
+        look_around: &LookAround,
+        state: &mut MatchState,
+        pos: usize,
+        next: Next,
+    ) -> bool {
+        let saved_slots = state.slots.clone();
+        let found = match look_around.direction {
+            LookDirection::Ahead => look_around
+                .alternatives
+                .iter()
+                .any(|alternative| alternative.match_str(state, pos, &mut |_, _| true)),
+            LookDirection::Behind { max_len } => {
+                // every start from `pos` back to max_len chars before it, nearest first
+                let mut starts = vec![pos];
+                while let Some((_, len)) = state.char_before(starts[starts.len() - 1]) {
+                    if starts.len() > max_len {
+                        break;
+                    }
+                    starts.push(starts[starts.len() - 1] - len);
+                }
+                starts.into_iter().any(|start| {
+                    look_around.alternatives.iter().any(|alternative| {
+                        alternative.match_str(state, start, &mut |_, end| end == pos)
+                    })
+                })
             }
-            // if the char doesn't match that is fine and return the input with no consuing for any char
-            None => Some(Remaining::Single(Some(s))),
+        };
+        if found != look_around.negated && next(state, pos) {
+            return true;
         }
+        state.slots = saved_slots;
+        false
     }
 
-    fn match_group<'a>(
-        tokens: &[Token],
-        str: &'a str,
-        remainings: &mut Vec<Option<&'a str>>,
-    ) -> Option<Remaining<'a>> {
-        if tokens.is_empty() {
-            return Some(Remaining::Single(Some(str)));
+    fn match_char_class(class: &CharClass, c: char, ignore_case: bool) -> bool {
+        match class {
+            // [^...] and \P{..} leave out every case of what they negate
+            CharClass::Not(class) => !Self::match_char_class(class, c, ignore_case),
+            _ if ignore_case => unicode::simple_case_folds(c)
+                .iter()
+                .chain([c].iter())
+                .any(|&c| Self::match_char_class(class, c, false)),
+            CharClass::Digit => unicode::is_digit(c),
+            CharClass::Identifier => unicode::is_word_char(c),
+            CharClass::Space => unicode::is_white_space(c),
+            CharClass::AsciiDigit => c.is_ascii_digit(),
+            CharClass::AsciiIdentifier => c.is_ascii_alphanumeric() || c == '_',
+            CharClass::AsciiSpace => c.is_ascii_whitespace() || c == '\x0b',
+            CharClass::Alnum => c.is_ascii_alphanumeric(),
+            CharClass::Alpha => c.is_ascii_alphabetic(),
+            CharClass::Blank => c == ' ' || c == '\t',
+            CharClass::Cntrl => c.is_ascii_control(),
+            CharClass::Graph => c.is_ascii_graphic(),
+            CharClass::Lower => c.is_ascii_lowercase(),
+            CharClass::Print => c.is_ascii_graphic() || c == ' ',
+            CharClass::Punct => c.is_ascii_punctuation(),
+            CharClass::Upper => c.is_ascii_uppercase(),
+            CharClass::XDigit => c.is_ascii_hexdigit(),
+            CharClass::Property(property) => property.contains(c),
         }
+    }
 
-        let (current_token, remaining_tokens) = tokens.split_first().unwrap();
-        if let Some(remaining) = current_token._match(str) {
-            match remaining {
-                Remaining::Single(Some(remaining_str)) => {
-                    Self::match_group(remaining_tokens, remaining_str, remainings)
-                }
-                Remaining::Multiple(remaining_strs) => {
-                    // Try each possible remaining string
-                    for remaining_str_opt in remaining_strs {
-                        if let Some(remaining_str) = remaining_str_opt {
-                            if let Some(Remaining::Single(s)) =
-                                Self::match_group(remaining_tokens, remaining_str, remainings)
-                            {
-                                remainings.push(s);
-                            }
-                        }
+    fn match_group_class(group_class: &GroupClass, c: char, ignore_case: bool) -> bool {
+        let (items, is_inverted) = match group_class {
+            GroupClass::MatchOne(items) => (items, false),
+            GroupClass::MatchNone(items) => (items, true),
+        };
+        let in_class = items.iter().any(|item| item.match_char(c, ignore_case));
+        in_class != is_inverted
+    }
+
+    fn match_group(group: &Group, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        group.alternatives.iter().any(|alternative| {
+            alternative.match_str(state, pos, &mut |state, end| match &group.capture {
+                Some(capture) => {
+                    // record the span, and put the old one back if the rest of the pattern fails
+                    let previous = state.slots[capture.index];
+                    state.slots[capture.index] = Some((pos, end));
+                    next(state, end) || {
+                        state.slots[capture.index] = previous;
+                        false
                     }
-                    Some(Remaining::Multiple(remainings.clone()))
                 }
-                _ => None,
+                None => next(state, end),
+            })
+        })
+    }
+
+    // greedy: take one more repetition first and only give it back if the rest fails
+    fn match_one_or_more(token: &Token, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        token._match(state, pos, &mut |state, after| {
+            // a repetition that consumed nothing can't lead anywhere new, stop looping
+            (after != pos && Self::match_one_or_more(token, state, after, next))
+                || next(state, after)
+        })
+    }
+
+    // if that is the case :   pattern = ca?at   and input = cat
+    // consuming the 'a' fails, so we have to try again without consuming it
+    fn match_one_or_none(token: &Token, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        token._match(state, pos, next) || next(state, pos)
+    }
+
+    // greedy {min,max}, `count` is the number of repetitions matched so far
+    fn match_repeat(
+        token: &Token,
+        min: usize,
+        max: Option<usize>,
+        count: usize,
+        state: &mut MatchState,
+        pos: usize,
+        next: Next,
+    ) -> bool {
+        if max == Some(count) {
+            return next(state, pos);
+        }
+        token._match(state, pos, &mut |state, after| {
+            // like in the Pike VM, one empty repetition past min is taken (its groups capture
+            // the empty text) and ends the loop, another one couldn't lead anywhere new
+            if after == pos && count >= min {
+                return next(state, after);
             }
-        } else {
-            None
-        }
-    }
-
-    fn match_excat_group<'a>(
-        tokens: &[Token],
-        str: &'a str,
-        remainings: &mut Vec<Option<&'a str>>,
-    ) -> Option<Remaining<'a>> {
-        if tokens.is_empty() {
-            return Some(Remaining::Single(Some(str)));
-        }
-
-        let (current_token, remaining_tokens) = tokens.split_first().unwrap();
-        if let Some(remaining) = current_token._match(str) {
-            match remaining {
-                Remaining::Single(Some(remaining_str)) => {
-                    Self::match_excat_group(remaining_tokens, remaining_str, remainings)
-                }
-                Remaining::Multiple(remaining_strs) => {
-                    // Try each possible remaining string
-                    for remaining_str_opt in remaining_strs {
-                        if let Some(remaining_str) = remaining_str_opt {
-                            if let Some(Remaining::Single(s)) =
-                                Self::match_excat_group(remaining_tokens, remaining_str, remainings)
-                            {
-                                if s.unwrap().len() == 0 {
-                                    remainings.push(s);
-                                }
-                            }
-                        }
-                    }
-                    Some(Remaining::Multiple(remainings.clone()))
-                }
-                _ => None,
+            Self::match_repeat(token, min, max, count + 1, state, after, next)
+        }) || (count >= min && next(state, pos))
+    }
+
+    // lazy {min,max}: try the rest of the pattern first and only repeat once more when it fails
+    fn match_repeat_lazy(
+        token: &Token,
+        min: usize,
+        max: Option<usize>,
+        count: usize,
+        state: &mut MatchState,
+        pos: usize,
+        next: Next,
+    ) -> bool {
+        if count >= min && next(state, pos) {
+            return true;
+        }
+        if max == Some(count) {
+            return false;
+        }
+        token._match(state, pos, &mut |state, after| {
+            if after == pos && count >= min {
+                return next(state, after);
+            }
+            Self::match_repeat_lazy(token, min, max, count + 1, state, after, next)
+        })
+    }
+
+    // take the first way the token matches and never come back to try another one
+    fn match_atomic(token: &Token, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        let saved_slots = state.slots.clone();
+        let mut end = None;
+        if !token._match(state, pos, &mut |_, after| {
+            end = Some(after);
+            true
+        }) {
+            return false;
+        }
+        match end {
+            Some(end) if next(state, end) => true,
+            _ => {
+                state.slots = saved_slots;
+                false
+            }
+        }
+    }
+
+    fn match_sequence(tokens: &[Token], state: &mut MatchState, pos: usize, next: Next) -> bool {
+        match tokens.split_first() {
+            None => next(state, pos),
+            Some((current_token, remaining_tokens)) => {
+                current_token._match(state, pos, &mut |state, after| {
+                    Self::match_sequence(remaining_tokens, state, after, next)
+                })
             }
-        } else {
-            None
         }
     }
 }
 
-#[derive(Debug)]
-pub enum ParseError {
-    Unclosed(String),      // e.g. missing ]
-    InvalidEscape(String), // e.g. \q
-    UnexpectedEof(String), // e.g. alone \
-    InvalidPattern(String),
-    InvalidAnchorType,
+// the rest of the pattern after a token, see Token::_match
+pub type Next<'n> = &'n mut dyn FnMut(&mut MatchState, usize) -> bool;
+
+// the span of every capture group, slot 0 is the whole match
+type Slots = Vec<Option<(usize, usize)>>;
+
+// everything the matcher needs while walking one input
+// the input is bytes, decoded as UTF-8 one char at a time (see utf8.rs)
+pub struct MatchState<'a> {
+    pub input: &'a [u8],
+    // span of each capture group, slot 0 is the whole match
+    pub slots: Vec<Option<(usize, usize)>>,
+    // what the search has spent so far, see Limits
+    steps: usize,
+    tokens: usize, // tried by the backtracker
+    depth: usize,
+    stack_base: usize, // where the stack was when the backtracker started
+    step_limit: Option<usize>,
+    backtrack_limit: usize,
+    stack_limit: usize,
+    // the limit the search ran into, it fails from then on
+    error: Option<MatchError>,
 }
 
-#[derive(Debug, PartialEq)]
+impl<'a> MatchState<'a> {
+    pub fn new(input: &'a [u8], group_count: usize) -> Self {
+        MatchState {
+            input,
+            slots: vec![None; group_count + 1],
+            steps: 0,
+            tokens: 0,
+            depth: 0,
+            stack_base: 0,
+            step_limit: None,
+            backtrack_limit: Limits::default().backtrack_limit,
+            stack_limit: Limits::default().stack_limit,
+            error: None,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: &Limits) -> Self {
+        self.step_limit = limits.step_limit;
+        self.backtrack_limit = limits.backtrack_limit;
+        self.stack_limit = limits.stack_limit;
+        self
+    }
+
+    // count `steps` more steps of the search, false once it went past the step limit
+    pub(crate) fn step(&mut self, steps: usize) -> bool {
+        self.steps = self.steps.saturating_add(steps);
+        if let Some(limit) = self.step_limit.filter(|limit| self.steps > *limit) {
+            self.error
+                .get_or_insert(MatchError::StepLimitExceeded { limit });
+        }
+        self.error.is_none()
+    }
+
+    // one more token tried by the backtracker, false once it went past the backtrack limit
+    fn backtrack(&mut self) -> bool {
+        self.tokens += 1;
+        if self.tokens > self.backtrack_limit {
+            let limit = self.backtrack_limit;
+            self.error
+                .get_or_insert(MatchError::BacktrackLimitExceeded { limit });
+        }
+        self.error.is_none()
+    }
+
+    // the result of a search that found `found`, unless it ran into a limit
+    pub(crate) fn result<T>(&self, found: T) -> Result<T, MatchError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(found),
+        }
+    }
+
+    // the char at or before `pos` and its length in bytes
+    fn char_at(&self, pos: usize) -> Option<(char, usize)> {
+        utf8::decode(&self.input[pos..])
+    }
+
+    fn char_before(&self, pos: usize) -> Option<(char, usize)> {
+        utf8::decode_last(&self.input[..pos])
+    }
+}
+
+// the top level alternatives of the pattern  (cat|dog  has two sub patterns)
+#[derive(Debug)]
 pub struct Pattern {
     pub sub_patterns: Vec<SubPattern>,
+    optimized: Vec<SubPattern>,   // what the engines run, see optimizer.rs
+    program: Option<Program>,     // None when only the backtracker can run the pattern
+    dfa: LazyDfa,                 // for the yes/no question of Pattern::matches
+    prefilter: Option<Prefilter>, // a literal every match contains
+    leftmost_longest: bool,       // see PatternOptions
+    limits: Limits,
 }
 
-#[derive(Debug, PartialEq)]
+// the same tokens make the same pattern, whatever its DFA has cached so far
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.sub_patterns == other.sub_patterns
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct SubPattern {
     pub tokens: Vec<Token>,
 }
 
+// settings that change how a pattern is compiled
+#[derive(Debug, Clone, Default)]
+pub struct PatternOptions {
+    pub ascii_only: bool,       // \d \w \s \b only know ASCII, like the classic grep
+    pub case_insensitive: bool, // like starting the pattern with (?i)
+    pub dot_matches_new_line: bool, // like starting the pattern with (?s)
+    pub multi_line: bool,       // like starting the pattern with (?m)
+    pub smart_case: bool,       // case insensitive unless the pattern has an upper case letter
+    pub syntax: Syntax,         // extended by default, the CLI defaults to basic like grep
+    // of the matches starting at the leftmost position, take the longest like POSIX tools,
+    // instead of the first one in the order of the alternatives and quantifiers
+    pub leftmost_longest: bool,
+    pub limits: Limits, // for patterns that come from people we don't trust
+}
+
 // ----------------------- Start point of Parsing ---------------------------------- //
 impl FromStr for Pattern {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let sub_patterns_strs: Vec<String> = Self::expand_pattern(s);
+        Self::with_options(s, &PatternOptions::default())
+    }
+}
 
-        let mut sub_patterns = vec![];
+impl Pattern {
+    pub fn new(sub_patterns: Vec<SubPattern>) -> Self {
+        let mut pattern = Pattern {
+            optimized: optimizer::optimize(&sub_patterns),
+            sub_patterns,
+            program: None,
+            dfa: LazyDfa::new(),
+            prefilter: None,
+            leftmost_longest: false,
+            limits: Limits::default(),
+        };
+        pattern.prefilter = Prefilter::new(&pattern.optimized);
+        pattern.program = Program::compile(&pattern.optimized, pattern.group_count());
+        pattern
+    }
 
-        for pattern in sub_patterns_strs {
-            let mut sub_pattern_tokens = vec![];
-            let mut chars = pattern.chars();
-            //start Parsing
-            while let Some(token) = Self::get_tokens(&mut chars)? {
-                sub_pattern_tokens.push(token);
+    pub fn with_options(s: &str, options: &PatternOptions) -> Result<Self, ParseError> {
+        match options.syntax {
+            Syntax::Basic => {
+                let (extended, offsets) = syntax::basic_to_extended_with_offsets(s);
+                // the error points at what was written, not at its translation
+                Self::parse(&extended, options).map_err(|mut e| {
+                    e.span = offsets[e.span.start]..offsets[e.span.end];
+                    e.pattern = s.to_string();
+                    // the groups are written \( \) in this syntax
+                    match e.kind {
+                        ParseErrorKind::UnclosedGroup => e.suggest("add a \\) to close the group"),
+                        ParseErrorKind::UnmatchedParen => {
+                            e.suggest("remove the \\ to match a literal )")
+                        }
+                        _ => e,
+                    }
+                })
             }
-            sub_patterns.push(SubPattern {
-                tokens: sub_pattern_tokens,
-            });
+            Syntax::Extended => Self::parse(s, options).map_err(|mut e| {
+                e.pattern = s.to_string();
+                e
+            }),
+        }
+    }
+
+    // parse a pattern in the extended syntax, the spans of the errors are offsets in `s`
+    fn parse(s: &str, options: &PatternOptions) -> Result<Self, ParseError> {
+        let mut chars = s.chars();
+        //start Parsing
+        let nest_limit = options.limits.nest_limit;
+        let mut sub_patterns =
+            Self::get_alternatives(&mut chars, nest_limit).map_err(|e| e.located(s))?;
+        // get_alternatives only stops before the end on a ')' that has no '(' for it
+        if chars.next().is_some() {
+            let end = s.len() - chars.as_str().len();
+            return Err(ParseError::at(
+                ParseErrorKind::UnmatchedParen,
+                "unmatched )".to_string(),
+                end - 1..end,
+            )
+            .suggest("escape it as \\) to match a literal )"));
+        }
+        Self::check_limits(&sub_patterns, &options.limits, s)?;
+        let mut names = vec![];
+        for sub_pattern in &mut sub_patterns {
+            Self::number_groups(&mut sub_pattern.tokens, &mut names, s)?;
+        }
+        for sub_pattern in &mut sub_patterns {
+            Self::resolve_back_references(&mut sub_pattern.tokens, &names, s)?;
+        }
+        if options.ascii_only {
+            for sub_pattern in &mut sub_patterns {
+                Self::make_ascii_only(&mut sub_pattern.tokens);
+            }
+        }
+        let case_insensitive = options.case_insensitive
+            || options.smart_case
+                && !sub_patterns
+                    .iter()
+                    .any(|sub_pattern| Self::has_upper_case(&sub_pattern.tokens));
+        let mut flags = Flags {
+            case_insensitive: Some(case_insensitive),
+            dot_matches_new_line: Some(options.dot_matches_new_line),
+            multi_line: Some(options.multi_line),
+        };
+        // the flags set in one alternative carry over to the next ones, like in a group
+        for sub_pattern in &mut sub_patterns {
+            Self::apply_flags(&mut sub_pattern.tokens, &mut flags);
         }
 
-        Ok(Self { sub_patterns })
+        let mut pattern = Self::new(sub_patterns);
+        pattern.leftmost_longest = options.leftmost_longest;
+        pattern.limits = options.limits;
+        Ok(pattern)
+    }
+
+    // the limits only the whole parsed pattern can be checked against
+    fn check_limits(
+        sub_patterns: &[SubPattern],
+        limits: &Limits,
+        s: &str,
+    ) -> Result<(), ParseError> {
+        let alternatives = sub_patterns.len()
+            + sub_patterns
+                .iter()
+                .map(|sub_pattern| Self::alternation_count(&sub_pattern.tokens))
+                .sum::<usize>();
+        if alternatives > limits.alternation_limit {
+            return Err(ParseError::at(
+                ParseErrorKind::TooManyAlternatives,
+                format!(
+                    "the pattern has {} alternatives, the limit is {}",
+                    alternatives, limits.alternation_limit
+                ),
+                0..s.len(),
+            ));
+        }
+        let size = nfa::compiled_size(sub_patterns);
+        if size > limits.size_limit {
+            return Err(ParseError::at(
+                ParseErrorKind::PatternTooLarge,
+                format!(
+                    "the compiled pattern would have {} instructions, the limit is {}",
+                    size, limits.size_limit
+                ),
+                0..s.len(),
+            )
+            .suggest("use smaller counts in {n,m}, or a + or * instead"));
+        }
+        Ok(())
+    }
+
+    // the alternatives of every group and lookaround in the tokens
+    fn alternation_count(tokens: &[Token]) -> usize {
+        tokens
+            .iter()
+            .map(|token| {
+                let own = match token {
+                    Token::Group(Group { alternatives, .. })
+                    | Token::LookAround(LookAround { alternatives, .. }) => alternatives.len(),
+                    _ => 0,
+                };
+                let nested: usize = token
+                    .children()
+                    .into_iter()
+                    .map(Self::alternation_count)
+                    .sum();
+                own + nested
+            })
+            .sum()
     }
 }
 
 impl Pattern {
-    fn expand_pattern(pattern: &str) -> Vec<String> {
-        fn helper(s: &str) -> Vec<String> {
-            if let Some(open) = s.find('(') {
-                let close = s[open..]
-                    .find(')')
-                    .map(|i| open + i)
-                    .expect("Unmatched '('");
-
-                let before = &s[..open];
-                let inside = &s[open + 1..close];
-                let after = &s[close + 1..];
-
-                let choices: Vec<&str> = if inside.contains('|') {
-                    inside.split('|').collect()
+    // ------------------------------------------------------------------------------//
+    //                                 Parsing Logic                                 //
+    // ------------------------------------------------------------------------------//
+
+    // parse `a|b|c` until the end of the pattern or the ')' closing the current group
+    // the ')' itself is left for the caller
+    // `nest_left` is how many more groups and lookarounds can be opened inside the current one
+    fn get_alternatives(
+        chars: &mut Chars,
+        nest_left: usize,
+    ) -> Result<Vec<SubPattern>, ParseError> {
+        let mut alternatives = vec![];
+        loop {
+            alternatives.push(Self::get_sub_pattern(chars, nest_left)?);
+            match get_next_char(chars) {
+                Some('|') => {
+                    chars.next(); // consume |
+                }
+                _ => break Ok(alternatives),
+            }
+        }
+    }
+
+    // parse one alternative
+    fn get_sub_pattern(chars: &mut Chars, nest_left: usize) -> Result<SubPattern, ParseError> {
+        let mut tokens = vec![];
+        while let Some(token) = Self::get_tokens(chars, nest_left)? {
+            tokens.push(token);
+        }
+        Ok(SubPattern { tokens })
+    }
+    // main Parsing Function, returns None at the end of the current alternative
+    fn get_tokens(chars: &mut Chars, nest_left: usize) -> Result<Option<Token>, ParseError> {
+        if let None | Some('|') | Some(')') = get_next_char(chars) {
+            return Ok(None);
+        }
+        let start = chars.as_str();
+        match chars.next() {
+            Some('\\') => {
+                let token = match chars.next().ok_or_else(|| trailing_backslash(start))? {
+                    c @ '1'..='9' => Token::BackReference(c as usize - '0' as usize),
+                    'k' => Self::get_back_reference_name(start, chars)?,
+                    'b' => Token::Assertion(Assertion::WordBoundary),
+                    'B' => Token::Assertion(Assertion::NotWordBoundary),
+                    'A' => Token::Assertion(Assertion::StartOfText),
+                    'z' => Token::Assertion(Assertion::EndOfText),
+                    c => Self::get_escape(start, c, chars)?,
+                };
+                match token {
+                    // assertions don't take quantifiers
+                    Token::Assertion(_) => Self::no_quantifier(chars, token).map(Some),
+                    token => Ok(Some(Self::get_quantifier(chars, token, nest_left)?)),
+                }
+            }
+            Some('(') => {
+                let nest_left = nest_left.checked_sub(1).ok_or_else(|| {
+                    ParseError::new(
+                        ParseErrorKind::NestingTooDeep,
+                        "groups are nested too deep".to_string(),
+                        start,
+                        chars.as_str(),
+                    )
+                })?;
+                if let Some(look_around) = Self::get_look_around(start, chars, nest_left)? {
+                    // lookarounds are zero-width, they don't take quantifiers
+                    return Self::no_quantifier(chars, Token::LookAround(look_around)).map(Some);
+                }
+                let flags = Self::get_flags(start, chars)?;
+                if let Some((flags, false)) = flags {
+                    // (?i) is zero-width, it doesn't take quantifiers
+                    return Self::no_quantifier(chars, Token::SetFlags(flags)).map(Some);
+                }
+                let is_atomic = chars.as_str().starts_with("?>");
+                let capture = if is_atomic {
+                    chars.nth(1); // consume ?>
+                    None
+                } else if flags.is_some() {
+                    None
                 } else {
-                    vec![inside]
+                    Self::get_group_kind(start, chars)?
                 };
-
-                let mut results = vec![];
-                for choice in choices {
-                    for rest in helper(after) {
-                        results.push(format!("{}{}{}", before, choice, rest));
-                    }
+                let mut alternatives = Self::get_alternatives(chars, nest_left)?;
+                if chars.next() != Some(')') {
+                    return Err(unclosed_group(start));
                 }
-                results
-            } else {
-                vec![s.to_string()]
+                // (?i:a|b) is (?:(?i)a|b), the flags last until the end of the group
+                if let Some((flags, _)) = flags {
+                    alternatives[0].tokens.insert(0, Token::SetFlags(flags));
+                }
+                let mut token = Token::Group(Group {
+                    alternatives,
+                    capture,
+                });
+                if is_atomic {
+                    token = Token::Atomic(Box::new(token));
+                }
+                Ok(Some(Self::get_quantifier(chars, token, nest_left)?))
             }
+            // assertions don't take quantifiers
+            Some('^') => {
+                Self::no_quantifier(chars, Token::Assertion(Assertion::StartOfLine)).map(Some)
+            }
+            Some('$') => {
+                Self::no_quantifier(chars, Token::Assertion(Assertion::EndOfLine)).map(Some)
+            }
+            Some('[') => {
+                let token = Self::get_group_tokens(start, chars)?;
+                Ok(Some(Self::get_quantifier(chars, token, nest_left)?))
+            }
+            Some('.') => Ok(Some(Self::get_quantifier(
+                chars,
+                Token::WildCard,
+                nest_left,
+            )?)),
+            Some(c) => Ok(Some(Self::get_quantifier(
+                chars,
+                Token::Literal(c),
+                nest_left,
+            )?)),
+            None => Ok(None),
         }
+    }
 
-        helper(pattern)
+    // parse what comes right after a '(' :  (?:...) doesn't capture, (?<name>...) is named
+    // the index is filled later by number_groups
+    // `start` is the pattern from the '('
+    fn get_group_kind(start: &str, chars: &mut Chars) -> Result<Option<CaptureGroup>, ParseError> {
+        if get_next_char(chars) != Some('?') {
+            return Ok(Some(CaptureGroup {
+                index: 0,
+                name: None,
+            }));
+        }
+        chars.next(); // consume ?
+        match chars.next() {
+            Some(':') => Ok(None),
+            Some('<') => Self::get_group_name(chars),
+            Some('P') if chars.next() == Some('<') => Self::get_group_name(chars),
+            _ => Err(ParseError::new(
+                ParseErrorKind::UnknownGroupType,
+                "unknown group type after (?".to_string(),
+                start,
+                chars.as_str(),
+            )
+            .suggest("use one of (?: (?<name> (?= (?! (?<= (?<! (?>, or escape the ( as \\(")),
+        }
     }
 
-    // ------------------------------------------------------------------------------//
-    //                                 Parsing Logic                                 //
-    // ------------------------------------------------------------------------------//
+    // parse (?=...) (?!...) (?<=...) (?<!...), the '(' is already consumed
+    fn get_look_around(
+        start: &str,
+        chars: &mut Chars,
+        nest_left: usize,
+    ) -> Result<Option<LookAround>, ParseError> {
+        let rest = chars.as_str();
+        let (behind, negated, prefix_len) = if rest.starts_with("?=") {
+            (false, false, 2)
+        } else if rest.starts_with("?!") {
+            (false, true, 2)
+        } else if rest.starts_with("?<=") {
+            (true, false, 3)
+        } else if rest.starts_with("?<!") {
+            (true, true, 3)
+        } else {
+            return Ok(None);
+        };
+        *chars = rest[prefix_len..].chars();
+        let alternatives = Self::get_alternatives(chars, nest_left)?;
+        if chars.next() != Some(')') {
+            return Err(unclosed_group(start));
+        }
+        let direction = if behind {
+            match Token::alternatives_max_len(&alternatives) {
+                Some(max_len) if max_len <= MAX_LOOK_BEHIND => LookDirection::Behind { max_len },
+                _ => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::LookBehindTooLong,
+                        format!(
+                            "lookbehind too long: it can match at most {} chars, \
+                             without unbounded repetitions or backreferences",
+                            MAX_LOOK_BEHIND
+                        ),
+                        start,
+                        chars.as_str(),
+                    )
+                    .suggest("bound its repetitions, a{1,10} instead of a+"))
+                }
+            }
+        } else {
+            LookDirection::Ahead
+        };
+        Ok(Some(LookAround {
+            alternatives,
+            direction,
+            negated,
+        }))
+    }
 
-    // main Parsing Function
-    fn get_tokens(chars: &mut Chars) -> Result<Option<Token>, ParseError> {
-        // check if SOL
-        let last_char = chars.as_str().chars().last();
-        let first_char = chars.as_str().chars().nth(0);
-        if let Some('$') = last_char {
-            if let Some('^') = first_char {
-                // Exact
-                let s1 = remove_char_at(0, chars);
-                let s2 = remove_char_at(s1.len() - 1, &mut s1.chars());
-                return Self::get_anchor_tokens(&mut s2.chars(), Anchor::Both);
-            }
-            //EOL
-            return Self::get_anchor_tokens(
-                &mut remove_char_at(chars.as_str().len() - 1, chars).chars(),
-                Anchor::End,
-            );
+    // parse the flags right after a '(' :  (?i)  (?-s)  (?i:...)  (?is-i)
+    // the bool is true when they start a group  (?i:...), then the ':' is consumed too
+    fn get_flags(start: &str, chars: &mut Chars) -> Result<Option<(Flags, bool)>, ParseError> {
+        let Some(rest) = chars.as_str().strip_prefix('?') else {
+            return Ok(None);
+        };
+        let end = rest
+            .find(|c: char| !c.is_ascii_lowercase() && c != '-')
+            .unwrap_or(rest.len());
+        let (written, after) = rest.split_at(end);
+        let scoped = match after.chars().next() {
+            _ if written.is_empty() => return Ok(None), // (?: (?< (?P< (?> ...
+            Some(')') => false,
+            Some(':') => true,
+            _ => {
+                return Err(ParseError::new(
+                    ParseErrorKind::Unterminated,
+                    "missing ) or : after the flags".to_string(),
+                    start,
+                    after,
+                )
+                .suggest(format!("close them as (?{})", written)))
+            }
+        };
+        let mut flags = Flags::default();
+        let mut enable = true;
+        for c in written.chars() {
+            match c {
+                '-' if enable => enable = false,
+                'i' => flags.case_insensitive = Some(enable),
+                's' => flags.dot_matches_new_line = Some(enable),
+                'm' => flags.multi_line = Some(enable),
+                _ => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::InvalidFlags,
+                        format!("unknown flag {} in (?{})", c, written),
+                        start,
+                        &after[1..],
+                    )
+                    .suggest("the flags are i, m and s"))
+                }
+            }
         }
-        //if not SOL do normal Parsing
-        match chars.next() {
-            Some('\\') => {
-                let mut token;
-                match chars
-                    .next()
-                    .ok_or(ParseError::UnexpectedEof(format!("Expcted char after \\")))?
-                {
-                    'd' => token = Token::CharClass(CharClass::Digit),
-                    'w' => token = Token::CharClass(CharClass::Identifier),
-                    '\\' => token = Token::Literal('\\'),
-                    c => {
-                        return Err(ParseError::InvalidEscape(format!(
-                            "\\ doesn't allow {} after it",
-                            c
-                        )))
+        if flags == Flags::default() || written.ends_with('-') {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidFlags,
+                format!("missing flags in (?{})", written),
+                start,
+                &after[1..],
+            )
+            .suggest("write flags after the -, like (?-i)"));
+        }
+        *chars = after[1..].chars();
+        Ok(Some((flags, scoped)))
+    }
+
+    // the '\\k' is already consumed  (\k<name>)
+    fn get_back_reference_name(start: &str, chars: &mut Chars) -> Result<Token, ParseError> {
+        if !chars.as_str().starts_with('<') {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidEscape,
+                "\\k must be followed by <name>".to_string(),
+                start,
+                chars.as_str(),
+            )
+            .suggest("write \\k<name>"));
+        }
+        chars.next(); // consume <
+        let rest = chars.as_str();
+        let end = rest.find('>').ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::Unterminated,
+                "missing > after \\k<".to_string(),
+                start,
+                "",
+            )
+        })?;
+        let name = rest[..end].to_string();
+        *chars = rest[end + 1..].chars();
+        Ok(Token::NamedBackReference(name))
+    }
+
+    // the '(?<' is already consumed
+    fn get_group_name(chars: &mut Chars) -> Result<Option<CaptureGroup>, ParseError> {
+        let rest = chars.as_str();
+        let end = rest.find('>').ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::Unterminated,
+                "missing > after group name".to_string(),
+                rest,
+                "",
+            )
+        })?;
+        let name = &rest[..end];
+        let mut name_chars = name.chars();
+        let valid = matches!(name_chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && name_chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidGroupName,
+                format!("invalid group name <{}>", name),
+                rest,
+                &rest[end..],
+            )
+            .suggest("a name starts with a letter or _, followed by letters, digits or _"));
+        }
+        *chars = rest[end + 1..].chars();
+        Ok(Some(CaptureGroup {
+            index: 0,
+            name: Some(name.to_string()),
+        }))
+    }
+
+    // give every capture group its number, in the order of their '(' in the pattern
+    // `names` holds the name of every group numbered so far, `pattern` is only for errors
+    fn number_groups(
+        tokens: &mut [Token],
+        names: &mut Vec<Option<String>>,
+        pattern: &str,
+    ) -> Result<(), ParseError> {
+        for token in tokens {
+            if let Token::Group(Group {
+                capture: Some(capture),
+                ..
+            }) = token
+            {
+                if let Some(name) = &capture.name {
+                    if names.contains(&Some(name.clone())) {
+                        let (open, open_p) = (format!("(?<{}>", name), format!("(?P<{}>", name));
+                        return Err(ParseError::at(
+                            ParseErrorKind::DuplicateGroupName,
+                            format!("duplicate group name <{}>", name),
+                            find_unescaped(pattern, &[&open, &open_p], 1),
+                        )
+                        .suggest("give each group its own name"));
                     }
                 }
-                let next_char = get_next_char(chars);
-                if let Some(char) = next_char {
-                    match char {
-                        '+' => {
-                            chars.next();
-                            Ok(Some(Token::OneORMore(Box::new(token))))
-                        }
-                        '?' => {
-                            chars.next();
-                            Ok(Some(Token::OneOrNone(Box::new(token))))
-                        }
-                        _ => Ok(Some(token)),
+                names.push(capture.name.clone());
+                capture.index = names.len();
+            }
+            for children in token.children_mut() {
+                Self::number_groups(children, names, pattern)?;
+            }
+        }
+        Ok(())
+    }
+
+    // once all groups are numbered, turn \k<name> into its number
+    // and reject references to groups that don't exist
+    fn resolve_back_references(
+        tokens: &mut [Token],
+        names: &[Option<String>],
+        pattern: &str,
+    ) -> Result<(), ParseError> {
+        for token in tokens {
+            match token {
+                Token::NamedBackReference(name) => {
+                    let index = names
+                        .iter()
+                        .position(|group_name| group_name.as_deref() == Some(name.as_str()))
+                        .ok_or_else(|| {
+                            let reference = format!("\\k<{}>", name);
+                            ParseError::at(
+                                ParseErrorKind::UndefinedGroup,
+                                format!("{} refers to a group that doesn't exist", reference),
+                                find_unescaped(pattern, &[reference.as_str()], 0),
+                            )
+                        })?;
+                    *token = Token::BackReference(index + 1);
+                }
+                Token::BackReference(index) if *index > names.len() => {
+                    let reference = format!("\\{}", index);
+                    return Err(ParseError::at(
+                        ParseErrorKind::UndefinedGroup,
+                        format!("{} refers to a group that doesn't exist", reference),
+                        find_unescaped(pattern, &[reference.as_str()], 0),
+                    )
+                    .suggest(format!("the pattern has {} groups", names.len())));
+                }
+                _ => {
+                    for children in token.children_mut() {
+                        Self::resolve_back_references(children, names, pattern)?;
                     }
-                } else {
-                    Ok(Some(token))
                 }
             }
-            Some('^') => Self::get_anchor_tokens(chars, Anchor::Start),
-            Some('[') => Self::get_group_tokens(chars),
-            Some(c) => {
-                let next_char = get_next_char(chars);
-                match next_char {
-                    Some('+') => {
-                        chars.next(); // consume +
-                        Ok(Some(Token::OneORMore(Box::new(Token::Literal(c)))))
+        }
+        Ok(())
+    }
+
+    // wrap what (?i) applies to into CaseInsensitive and turn . into AnyChar under (?s)
+    // `flags` are the flags in effect so far
+    fn apply_flags(tokens: &mut [Token], flags: &mut Flags) {
+        for token in tokens {
+            match token {
+                Token::SetFlags(set) => *flags = flags.merge(*set),
+                Token::WildCard => {
+                    if flags.dot_matches_new_line == Some(true) {
+                        *token = Token::AnyChar;
                     }
-                    Some('?') => {
-                        chars.next(); // consume ?
-                        Ok(Some(Token::OneOrNone(Box::new(Token::Literal(c)))))
+                }
+                Token::Assertion(assertion) if flags.multi_line == Some(true) => match assertion {
+                    Assertion::StartOfLine => *assertion = Assertion::MultiLineStart,
+                    Assertion::EndOfLine => *assertion = Assertion::MultiLineEnd,
+                    _ => {}
+                },
+                // a literal without other cases (a digit, '-' ...) doesn't need the wrapper
+                Token::Literal(c) if unicode::simple_case_folds(*c).is_empty() => {}
+                Token::Literal(_)
+                | Token::CharClass(_)
+                | Token::GroupClass(_)
+                | Token::BackReference(_) => {
+                    if flags.case_insensitive == Some(true) {
+                        let inner = std::mem::replace(token, Token::WildCard);
+                        *token = Token::CaseInsensitive(Box::new(inner));
+                    }
+                }
+                _ => {
+                    // what is set inside a group stays inside it
+                    let mut inner_flags = *flags;
+                    for children in token.children_mut() {
+                        Self::apply_flags(children, &mut inner_flags);
                     }
-                    _ => Ok(Some(Token::Literal(c))),
                 }
             }
-            //Some('.') => Ok(Some(Token::WildCard)),
-            None => Ok(None),
         }
     }
 
-    fn get_group_tokens(chars: &mut Chars) -> Result<Option<Token>, ParseError> {
+    // for smart case: escapes like \W or \P{Lu} don't count, only letters written as they are
+    fn has_upper_case(tokens: &[Token]) -> bool {
+        tokens.iter().any(|token| match token {
+            Token::Literal(c) => c.is_uppercase(),
+            Token::Range(start, end) => start.is_uppercase() || end.is_uppercase(),
+            Token::GroupClass(GroupClass::MatchOne(items) | GroupClass::MatchNone(items)) => {
+                Self::has_upper_case(items)
+            }
+            _ => token
+                .children()
+                .iter()
+                .any(|children| Self::has_upper_case(children)),
+        })
+    }
+
+    // swap the Unicode \d \w \s \b \B for their ASCII versions
+    fn make_ascii_only(tokens: &mut [Token]) {
+        for token in tokens {
+            match token {
+                Token::CharClass(class) => Self::make_class_ascii_only(class),
+                Token::GroupClass(GroupClass::MatchOne(items) | GroupClass::MatchNone(items)) => {
+                    Self::make_ascii_only(items)
+                }
+                Token::Assertion(assertion) => match assertion {
+                    Assertion::WordBoundary => *assertion = Assertion::AsciiWordBoundary,
+                    Assertion::NotWordBoundary => *assertion = Assertion::AsciiNotWordBoundary,
+                    _ => {}
+                },
+                _ => {
+                    for children in token.children_mut() {
+                        Self::make_ascii_only(children);
+                    }
+                }
+            }
+        }
+    }
+
+    // \p{..} is explicit about what it wants, so it stays as it is
+    fn make_class_ascii_only(class: &mut CharClass) {
+        match class {
+            CharClass::Digit => *class = CharClass::AsciiDigit,
+            CharClass::Identifier => *class = CharClass::AsciiIdentifier,
+            CharClass::Space => *class = CharClass::AsciiSpace,
+            CharClass::Not(class) => Self::make_class_ascii_only(class),
+            _ => {}
+        }
+    }
+
+    // wrap the token we just parsed if a quantifier follows it  (a+  [ab]?  \d*  (ab){2,3})
+    // a '?' after the quantifier makes it lazy, a '+' makes it possessive
+    // a quantifier after that repeats the whole again like in GNU grep: a** is a*, a{2}{3} is a{6}
+    // every one of them nests the tree one level deeper, so they count against `nest_left`
+    fn get_quantifier(
+        chars: &mut Chars,
+        token: Token,
+        nest_left: usize,
+    ) -> Result<Token, ParseError> {
+        let quantifier = match get_next_char(chars) {
+            Some('+') => Token::OneORMore(Box::new(token)),
+            Some('?') => Token::OneOrNone(Box::new(token)),
+            Some('*') => Token::ZeroOrMore(Box::new(token)),
+            Some('{') => match Self::get_repeat_range(chars)? {
+                Some((min, max)) => Token::Repeat(Box::new(token), min, max),
+                None => return Ok(token), // not a counted repetition, the '{' is a literal
+            },
+            _ => return Ok(token),
+        };
+        if !matches!(quantifier, Token::Repeat(..)) {
+            chars.next(); // consume + ? or *, get_repeat_range already consumed the {...}
+        }
+        let quantifier = match get_next_char(chars) {
+            Some('?') => {
+                chars.next(); // consume ?
+                Token::Lazy(Box::new(quantifier))
+            }
+            Some('+') => {
+                chars.next(); // consume +
+                Token::Atomic(Box::new(quantifier))
+            }
+            _ => quantifier,
+        };
+        let is_stacked = match get_next_char(chars) {
+            Some('*' | '+' | '?') => true,
+            Some('{') => Self::get_repeat_range(&mut chars.clone())?.is_some(),
+            _ => false,
+        };
+        if !is_stacked {
+            return Ok(quantifier);
+        }
+        let start = chars.as_str();
+        let nest_left = nest_left.checked_sub(1).ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::NestingTooDeep,
+                "quantifiers are stacked too deep".to_string(),
+                start,
+                &start[1..],
+            )
+        })?;
+        Self::get_quantifier(chars, quantifier, nest_left)
+    }
+
+    // assertions, lookarounds and (?i) are zero-width, a quantifier after them has nothing
+    // to repeat: it is an error rather than a literal  ^*  \b+  (?=a){2}
+    fn no_quantifier(chars: &Chars, token: Token) -> Result<Token, ParseError> {
+        let rest = chars.as_str();
+        let len = match rest.chars().next() {
+            Some('*' | '+' | '?') => 1,
+            Some('{') => {
+                let mut lookahead = chars.clone();
+                match Self::get_repeat_range(&mut lookahead)? {
+                    Some(_) => rest.len() - lookahead.as_str().len(),
+                    None => 0,
+                }
+            }
+            _ => 0,
+        };
+        if len == 0 {
+            return Ok(token);
+        }
+        let quantifier = &rest[..len];
+        let c = &quantifier[..1];
+        Err(ParseError::new(
+            ParseErrorKind::InvalidRepetition,
+            format!(
+                "{} has nothing to repeat, what comes before it is zero-width",
+                quantifier
+            ),
+            rest,
+            &rest[len..],
+        )
+        .suggest(format!("escape it as \\{} to match a literal {}", c, c)))
+    }
+
+    // parse {n} {n,} {n,m}, the iterator is only moved when it really is one
+    fn get_repeat_range(chars: &mut Chars) -> Result<Option<(usize, Option<usize>)>, ParseError> {
+        let rest = chars.as_str();
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+        let inside = &rest[1..end];
+        let parse_count = |count: &str| -> Option<usize> {
+            if count.is_empty() || !count.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            // anything too long to parse is way above the limit anyway
+            Some(count.parse().unwrap_or(usize::MAX))
+        };
+        let (min, max) = match inside.split_once(',') {
+            None => match parse_count(inside) {
+                Some(count) => (count, Some(count)),
+                None => return Ok(None),
+            },
+            Some((min, "")) => match parse_count(min) {
+                Some(min) => (min, None),
+                None => return Ok(None),
+            },
+            Some((min, max)) => match (parse_count(min), parse_count(max)) {
+                (Some(min), Some(max)) => (min, Some(max)),
+                _ => return Ok(None),
+            },
+        };
+        let invalid = |message: String| {
+            ParseError::new(
+                ParseErrorKind::InvalidRepetition,
+                message,
+                rest,
+                &rest[end + 1..],
+            )
+        };
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return Err(invalid(format!(
+                "repetition {{{}}} is too large, the limit is {}",
+                inside, MAX_REPEAT
+            ))
+            .suggest(format!("use counts up to {}", MAX_REPEAT)));
+        }
+        if let Some(max) = max.filter(|max| *max < min) {
+            return Err(invalid(format!(
+                "invalid repetition {{{}}}, min is greater than max",
+                inside
+            ))
+            .suggest(format!("write it as {{{},{}}}", max, min)));
+        }
+        *chars = rest[end + 1..].chars();
+        Ok(Some((min, max)))
+    }
+
+    // parse a bracket expression, the '[' is already consumed
+    // [abc]  [^abc]  [a-z0-9]  [\d\]\-]  [[:alpha:]]  []abc]
+    // `start` is the pattern from the '['
+    fn get_group_tokens(start: &str, chars: &mut Chars) -> Result<Token, ParseError> {
         let mut tokens = vec![];
         let is_inverted = match get_next_char(chars) {
             Some('^') => {
@@ -361,145 +1513,526 @@ impl Pattern {
             }
             _ => false,
         };
+        // a ']' right after '[' or '[^' is a literal, not the end of the class  []a]  []-a]
+        let mut is_first = true;
         loop {
-            match chars.next() {
+            let item_start = chars.as_str();
+            let item = match chars.next() {
+                Some(']') if is_first => Token::Literal(']'),
                 Some(']') => {
                     if is_inverted {
-                        break Ok(Some(Token::GroupClass(GroupClass::MatchNone(tokens))));
+                        break Ok(Token::GroupClass(GroupClass::MatchNone(tokens)));
                     } else {
-                        break Ok(Some(Token::GroupClass(GroupClass::MatchOne(tokens))));
+                        break Ok(Token::GroupClass(GroupClass::MatchOne(tokens)));
+                    }
+                }
+                Some('[') if get_next_char(chars) == Some(':') => {
+                    chars.next(); // consume ':'
+                    Self::get_posix_class(item_start, chars)?
+                }
+                Some('\\') => Self::get_class_escape(item_start, chars)?,
+                Some(c) => Token::Literal(c),
+                None => return Err(unclosed_class(start)),
+            };
+            is_first = false;
+            // a '-' between two chars makes a range, at the end of the class it is a literal
+            let mut lookahead = chars.clone();
+            match (item, lookahead.next(), lookahead.next()) {
+                (Token::Literal(first), Some('-'), Some(next)) if next != ']' => {
+                    chars.next(); // consume '-'
+                    let end_start = chars.as_str();
+                    let class_ends_range = |chars: &Chars| {
+                        ParseError::new(
+                            ParseErrorKind::InvalidRange,
+                            format!("a character class can't end the range {}-", first),
+                            item_start,
+                            chars.as_str(),
+                        )
+                        .suggest(format!(
+                            "escape the - as \\- to match {}, - and the class",
+                            first
+                        ))
+                    };
+                    let end = match chars.next() {
+                        Some('\\') => Self::get_class_escape(end_start, chars)?,
+                        Some('[') if get_next_char(chars) == Some(':') => {
+                            return Err(class_ends_range(chars))
+                        }
+                        Some(c) => Token::Literal(c),
+                        None => return Err(unclosed_class(start)),
+                    };
+                    match end {
+                        Token::Literal(last) if first <= last => {
+                            tokens.push(Token::Range(first, last))
+                        }
+                        Token::Literal(last) => {
+                            return Err(ParseError::new(
+                                ParseErrorKind::InvalidRange,
+                                format!(
+                                    "invalid range {}-{}, start is greater than end",
+                                    first, last
+                                ),
+                                item_start,
+                                chars.as_str(),
+                            )
+                            .suggest(format!("write the range as {}-{}", last, first)))
+                        }
+                        _ => return Err(class_ends_range(chars)),
                     }
                 }
-                Some(c) => tokens.push(Token::Literal(c)),
-                None => return Err(ParseError::Unclosed(format!("Missing ]"))),
+                (item, _, _) => tokens.push(item),
             }
         }
     }
 
-    fn get_anchor_tokens(
-        chars: &mut Chars,
-        AnchorType: Anchor,
-    ) -> Result<Option<Token>, ParseError> {
-        let mut tokens = vec![];
-        while let Some(token) = Self::get_tokens(chars)? {
-            tokens.push(token);
+    // escapes allowed inside [ ] : the same as outside, except for references and assertions
+    // `start` is the pattern from the \
+    fn get_class_escape(start: &str, chars: &mut Chars) -> Result<Token, ParseError> {
+        let c = chars.next().ok_or_else(|| trailing_backslash(start))?;
+        Self::get_escape(start, c, chars)
+    }
+
+    // the escapes that mean the same thing inside and outside [ ], `c` is the char after the \
+    //   \d \D \w \W \s \S      classes and their negations
+    //   \t \n \r \f \v \a \e   control chars
+    //   \xHH \x{H..} \u{H..}  code points
+    //   \. \( \[ \+ \$ ...    any ASCII punctuation is itself
+    // `start` is the pattern from the \
+    fn get_escape(start: &str, c: char, chars: &mut Chars) -> Result<Token, ParseError> {
+        match c {
+            'd' => Ok(Token::CharClass(CharClass::Digit)),
+            'D' => Ok(Token::CharClass(CharClass::Not(Box::new(CharClass::Digit)))),
+            'w' => Ok(Token::CharClass(CharClass::Identifier)),
+            'W' => Ok(Token::CharClass(CharClass::Not(Box::new(
+                CharClass::Identifier,
+            )))),
+            's' => Ok(Token::CharClass(CharClass::Space)),
+            'S' => Ok(Token::CharClass(CharClass::Not(Box::new(CharClass::Space)))),
+            'p' | 'P' => {
+                let property = Self::get_property(start, chars)?;
+                match c {
+                    'p' => Ok(Token::CharClass(property)),
+                    _ => Ok(Token::CharClass(CharClass::Not(Box::new(property)))),
+                }
+            }
+            't' => Ok(Token::Literal('\t')),
+            'n' => Ok(Token::Literal('\n')),
+            'r' => Ok(Token::Literal('\r')),
+            'f' => Ok(Token::Literal('\x0c')),
+            'v' => Ok(Token::Literal('\x0b')),
+            'a' => Ok(Token::Literal('\x07')),
+            'e' => Ok(Token::Literal('\x1b')),
+            'x' | 'u' => Self::get_code_point(start, c, chars).map(Token::Literal),
+            c if c.is_ascii_punctuation() => Ok(Token::Literal(c)),
+            c => Err(ParseError::new(
+                ParseErrorKind::InvalidEscape,
+                format!("\\ doesn't allow {} after it", c),
+                start,
+                chars.as_str(),
+            )
+            .suggest(format!("remove the \\ to match a literal {}", c))),
         }
-        if tokens.len() == 0 {
-            return Err(ParseError::InvalidPattern(
-                "No thing after ^ or nothing before$".to_string(),
-            ));
+    }
+
+    // the '\\p' is already consumed:  \p{Greek}  \p{gc=Lu}  or a one letter category \pL
+    fn get_property(start: &str, chars: &mut Chars) -> Result<CharClass, ParseError> {
+        let rest = chars.as_str();
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced.find('}').ok_or_else(|| {
+                    ParseError::new(
+                        ParseErrorKind::Unterminated,
+                        "missing } after \\p{".to_string(),
+                        start,
+                        "",
+                    )
+                })?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => match rest.chars().next() {
+                Some(c) => rest.split_at(c.len_utf8()),
+                None => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::Unterminated,
+                        "expected a property name after \\p".to_string(),
+                        start,
+                        "",
+                    )
+                    .suggest("write \\p{Name}, like \\p{Greek}"))
+                }
+            },
+        };
+        let property = unicode::property(name).ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::UnknownClass,
+                format!("unknown Unicode property {}", name),
+                start,
+                after,
+            )
+        })?;
+        *chars = after.chars();
+        Ok(CharClass::Property(property))
+    }
+
+    // \xHH (exactly two hex digits) or \x{H..} / \u{H..} (one to six hex digits)
+    fn get_code_point(start: &str, escape: char, chars: &mut Chars) -> Result<char, ParseError> {
+        let rest = chars.as_str();
+        let invalid = |message: String, to: &str| {
+            ParseError::new(ParseErrorKind::InvalidEscape, message, start, to)
+        };
+        let (digits, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced.find('}').ok_or_else(|| {
+                    ParseError::new(
+                        ParseErrorKind::Unterminated,
+                        format!("missing }} after \\{}{{", escape),
+                        start,
+                        "",
+                    )
+                })?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None if escape == 'x' => (rest.get(..2).unwrap_or(rest), rest.get(2..).unwrap_or("")),
+            None => {
+                return Err(
+                    invalid("\\u must be followed by {hex digits}".to_string(), rest)
+                        .suggest("write \\u{41}"),
+                )
+            }
+        };
+        let is_braced = rest.starts_with('{');
+        let valid_length = if is_braced {
+            (1..=6).contains(&digits.len())
         } else {
-            match AnchorType {
-                Anchor::Start => Ok(Some(Token::SOL(tokens))),
-                Anchor::End => {
-                    tokens.reverse();
-                    Ok(Some(Token::EOL(tokens)))
+            digits.len() == 2
+        };
+        if !valid_length || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            let error = invalid(
+                format!("invalid hex digits '{}' after \\{}", digits, escape),
+                after,
+            );
+            return Err(match is_braced {
+                true => error.suggest("write one to six hex digits, like \\x{e9}"),
+                false => error.suggest("write two hex digits, like \\x41, or use \\x{..}"),
+            });
+        }
+        let code_point = u32::from_str_radix(digits, 16).unwrap();
+        let c = char::from_u32(code_point).ok_or_else(|| {
+            invalid(
+                format!("\\{}{{{}}} is not a valid code point", escape, digits),
+                after,
+            )
+        })?;
+        *chars = after.chars();
+        Ok(c)
+    }
+
+    // parse a POSIX named class, the '[:' is already consumed  ([:alpha:])
+    // `start` is the pattern from the '[' of the class
+    fn get_posix_class(start: &str, chars: &mut Chars) -> Result<Token, ParseError> {
+        let rest = chars.as_str();
+        let end = rest.find(":]").ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::Unterminated,
+                "missing :] after [:".to_string(),
+                start,
+                "",
+            )
+        })?;
+        let class = match &rest[..end] {
+            "alnum" => CharClass::Alnum,
+            "alpha" => CharClass::Alpha,
+            "blank" => CharClass::Blank,
+            "cntrl" => CharClass::Cntrl,
+            "digit" => CharClass::AsciiDigit,
+            "graph" => CharClass::Graph,
+            "lower" => CharClass::Lower,
+            "print" => CharClass::Print,
+            "punct" => CharClass::Punct,
+            "space" => CharClass::AsciiSpace,
+            "upper" => CharClass::Upper,
+            "xdigit" => CharClass::XDigit,
+            name => {
+                return Err(ParseError::new(
+                    ParseErrorKind::UnknownClass,
+                    format!("unknown POSIX class [:{}:]", name),
+                    start,
+                    &rest[end + 2..],
+                )
+                .suggest(
+                    "the classes are alnum alpha blank cntrl digit graph lower print punct \
+                     space upper xdigit",
+                ))
+            }
+        };
+        *chars = rest[end + 2..].chars();
+        Ok(Token::CharClass(class))
+    }
+
+    // ------------------------------------------------------------------------------//
+    //                                 Matching Logic                                //
+    // ------------------------------------------------------------------------------//
+
+    // start point of matching
+    // the lazy DFA answers without spans, unless its cache thrashes
+    pub fn matches(&self, input: &str) -> bool {
+        self.matches_bytes(input.as_bytes())
+    }
+
+    // the same on raw bytes, which don't have to be valid UTF-8
+    // a search that goes past one of the Limits finds nothing, see try_matches_bytes
+    pub fn matches_bytes(&self, input: &[u8]) -> bool {
+        self.try_matches_bytes(input).unwrap_or(false)
+    }
+
+    // the same, but going past the step or recursion limit is an error
+    pub fn try_matches_bytes(&self, input: &[u8]) -> Result<bool, MatchError> {
+        let Some(start) = self.first_candidate(input) else {
+            return Ok(false);
+        };
+        if let Some(program) = &self.program {
+            if let Some(is_match) = self.dfa.is_match(program, input, start) {
+                return Ok(is_match);
+            }
+        }
+        let mut state = self.new_state(input);
+        let found = self.find_at(&mut state, 0);
+        state.result(found)
+    }
+
+    // the leftmost match and the span of every capture group in it
+    pub fn captures<'h>(&self, input: &'h str) -> Option<Captures<'h>> {
+        let slots = self.find_slots(input.as_bytes(), 0).ok()??;
+        Some(Captures::new(input, slots, self.group_names()))
+    }
+
+    pub fn captures_bytes<'h>(&self, input: &'h [u8]) -> Option<Captures<'h, [u8]>> {
+        self.try_captures_bytes(input).ok()?
+    }
+
+    pub fn try_captures_bytes<'h>(
+        &self,
+        input: &'h [u8],
+    ) -> Result<Option<Captures<'h, [u8]>>, MatchError> {
+        let slots = self.find_slots(input, 0)?;
+        Ok(slots.map(|slots| Captures::new(input, slots, self.group_names())))
+    }
+
+    // the leftmost match only
+    pub fn find<'h>(&self, input: &'h str) -> Option<Match<'h>> {
+        self.captures(input).and_then(|captures| captures.get(0))
+    }
+
+    pub fn find_bytes<'h>(&self, input: &'h [u8]) -> Option<Match<'h, [u8]>> {
+        self.captures_bytes(input)
+            .and_then(|captures| captures.get(0))
+    }
+
+    // the leftmost match starting at `start` or later, the text before it is still seen
+    // by the assertions and lookbehinds  (\b at `start`)
+    pub fn find_bytes_at<'h>(&self, input: &'h [u8], start: usize) -> Option<Match<'h, [u8]>> {
+        self.try_find_bytes_at(input, start).ok()?
+    }
+
+    pub fn try_find_bytes_at<'h>(
+        &self,
+        input: &'h [u8],
+        start: usize,
+    ) -> Result<Option<Match<'h, [u8]>>, MatchError> {
+        let slots = self.find_slots(input, start)?;
+        Ok(slots.and_then(|slots| Captures::new(input, slots, self.group_names()).get(0)))
+    }
+
+    fn find_slots(&self, input: &[u8], start: usize) -> Result<Option<Slots>, MatchError> {
+        let mut state = self.new_state(input);
+        let slots = self
+            .find_at(&mut state, start)
+            .then(|| std::mem::take(&mut state.slots));
+        state.result(slots)
+    }
+
+    fn new_state<'h>(&self, input: &'h [u8]) -> MatchState<'h> {
+        MatchState::new(input, self.group_count()).with_limits(&self.limits)
+    }
+
+    // on success slot 0 of the state holds the span of the whole match
+    fn find_at(&self, state: &mut MatchState, start: usize) -> bool {
+        let Some(candidate) = self.first_candidate(&state.input[start..]) else {
+            return false;
+        };
+        let start = start + candidate;
+        match &self.program {
+            Some(program) => program.search(state, start, self.leftmost_longest),
+            // backreferences, lookarounds and atomic groups
+            None => self.backtrack(state, start),
+        }
+    }
+
+    // no match starts before this offset, None when the prefilter already knows there is none
+    fn first_candidate(&self, input: &[u8]) -> Option<usize> {
+        match &self.prefilter {
+            Some(prefilter) => prefilter.first_candidate(input),
+            None => Some(0),
+        }
+    }
+
+    // try every start position from the left, anchored tokens reject the ones they don't accept
+    // exponential on patterns like (a?){25}a{25}, which is why find_at prefers the Pike VM
+    pub(crate) fn backtrack(&self, state: &mut MatchState, start: usize) -> bool {
+        let mut start = start;
+        loop {
+            let found = if self.leftmost_longest {
+                self.backtrack_longest(state, start)
+            } else {
+                self.optimized.iter().any(|sub_pattern| {
+                    sub_pattern.match_str(state, start, &mut |state, end| {
+                        state.slots[0] = Some((start, end));
+                        true
+                    })
+                })
+            };
+            if found {
+                return true;
+            }
+            if state.error.is_some() {
+                return false;
+            }
+            match state.char_at(start) {
+                Some((_, len)) => start += len,
+                None => return false,
+            }
+        }
+    }
+
+    // every way to match from `start` is tried, the one that ends last is kept
+    fn backtrack_longest(&self, state: &mut MatchState, start: usize) -> bool {
+        let mut longest: Option<Vec<Option<(usize, usize)>>> = None;
+        for sub_pattern in &self.optimized {
+            sub_pattern.match_str(state, start, &mut |state, end| {
+                if longest
+                    .as_ref()
+                    .map_or(true, |slots| slots[0] < Some((start, end)))
+                {
+                    let mut slots = state.slots.clone();
+                    slots[0] = Some((start, end));
+                    longest = Some(slots);
                 }
-                Anchor::Both => Ok(Some(Token::Exact(tokens))),
-                _ => Err(ParseError::InvalidAnchorType),
+                false // go on with the other ways
+            });
+        }
+        match longest {
+            Some(slots) => {
+                state.slots = slots;
+                true
             }
+            None => false,
         }
     }
 
-    // ------------------------------------------------------------------------------//
-    //                                 Matching Logic                                //
-    // ------------------------------------------------------------------------------//
+    // backreferences, lookarounds and atomic groups can only be run by the backtracker
+    // the tokens with literal runs merged and alternatives factored, as the engines run them
+    pub fn optimized(&self) -> &[SubPattern] {
+        &self.optimized
+    }
 
-    // start point of matching
-    pub fn matches(&self, input: &str) -> bool {
-        let mut sub_pattern_matched = false;
-        for sub_pattern in &self.sub_patterns {
-            // just a Closure to call it  in the next match
-            let exhaustive_match = |input: &str| -> bool {
-                input
-                    .char_indices()
-                    .map(|(i, _)| &input[i..])
-                    .any(|substr| sub_pattern.match_str(substr).0)
-            };
+    pub(crate) fn needs_backtracking(&self) -> bool {
+        self.program.is_none()
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.group_names().len()
+    }
 
-            match &sub_pattern.tokens[0] {
-                Token::SOL(_) => sub_pattern_matched |= sub_pattern.match_str(input).0,
-                Token::EOL(_) => {
-                    let reversed_str: String = input.chars().rev().collect();
-                    sub_pattern_matched |= sub_pattern.match_str(reversed_str.as_str()).0;
+    // the name of every capture group (None when unnamed), group 1 first
+    pub fn group_names(&self) -> Vec<Option<String>> {
+        fn collect(tokens: &[Token], names: &mut Vec<Option<String>>) {
+            for token in tokens {
+                if let Token::Group(Group {
+                    capture: Some(capture),
+                    ..
+                }) = token
+                {
+                    names.push(capture.name.clone());
                 }
-                Token::Exact(_) => {
-                    let (mathced, remaining_str) = sub_pattern.match_str(input);
-                    if remaining_str.len() != 0 {
-                        sub_pattern_matched |= false;
-                    } else {
-                        sub_pattern_matched |= mathced;
-                    }
+                for children in token.children() {
+                    collect(children, names);
                 }
-                _ => sub_pattern_matched |= exhaustive_match(input),
-            };
-
-            if sub_pattern_matched {
-                return true; // stop at first success
             }
         }
-        false
+        let mut names = vec![];
+        for sub_pattern in &self.sub_patterns {
+            collect(&sub_pattern.tokens, &mut names);
+        }
+        names
     }
 }
 
 impl SubPattern {
-    fn match_str<'a>(&self, input_line: &'a str) -> (bool, &'a str) {
-        fn match_tokens<'a>(tokens: &[Token], input: &'a str) -> (bool, &'a str) {
-            if tokens.is_empty() {
-                return (true, input);
-            }
-
-            let (first, rest_tokens) = tokens.split_first().unwrap();
-            let mut final_remaning: &str = "";
-            if let Some(remaining) = first._match(input) {
-                match remaining {
-                    Remaining::Single(Some(remaining_str)) => {
-                        final_remaning = remaining_str;
-                        match_tokens(rest_tokens, remaining_str)
-                    }
-                    Remaining::Multiple(remaining_strs) => {
-                        for remaining in remaining_strs {
-                            if let Some(remaining_str) = remaining {
-                                final_remaning = remaining_str;
-                                let (matched, new_remaning_str) =
-                                    match_tokens(rest_tokens, remaining_str);
-                                if matched {
-                                    return (true, new_remaning_str); // found a successful match
-                                }
-                            }
-                        }
-
-                        (false, final_remaning)
-                    }
-
-                    _ => (false, final_remaning),
-                }
-                // Try all remaining options
-            } else {
-                (false, final_remaning) // no match found
-            }
-        }
-
-        return match_tokens(&self.tokens, input_line);
+    fn match_str(&self, state: &mut MatchState, pos: usize, next: Next) -> bool {
+        Token::match_sequence(&self.tokens, state, pos, next)
     }
 }
 
-fn remove_char_at<'a>(idx: usize, chars: &'a mut Chars) -> String {
-    let mut str: String = chars.collect();
-    str.remove(idx);
-    str
-}
 // get_next char and keeping the parser iterator as it was before calling this function
 fn get_next_char(chars: &mut Chars) -> Option<char> {
     let mut clone = chars.clone();
     clone.next()
 }
-fn skip<'a>(s: &'a str, chars: usize) -> &'a str {
-    let mut iter = s.chars();
-    for _ in 0..chars {
-        iter.next();
+
+// `start` is the pattern from the '(' of the group
+fn unclosed_group(start: &str) -> ParseError {
+    ParseError::new(
+        ParseErrorKind::UnclosedGroup,
+        "missing )".to_string(),
+        start,
+        "",
+    )
+    .suggest("add a ) to close the group")
+}
+
+// `start` is the pattern from the '[' of the class
+fn unclosed_class(start: &str) -> ParseError {
+    ParseError::new(
+        ParseErrorKind::UnclosedClass,
+        "missing ]".to_string(),
+        start,
+        "",
+    )
+    .suggest("add a ] to close the class, or escape the [ as \\[")
+}
+
+// `start` is the pattern from the \
+fn trailing_backslash(start: &str) -> ParseError {
+    ParseError::new(
+        ParseErrorKind::TrailingBackslash,
+        "expected a char after \\".to_string(),
+        start,
+        "",
+    )
+    .suggest("escape it as \\\\ to match a literal \\")
+}
+
+// the span of the nth of the `needles` in the pattern, skipping the escaped chars,
+// for the errors found once parsing is over and the tokens don't know where they were
+fn find_unescaped(pattern: &str, needles: &[&str], nth: usize) -> Range<usize> {
+    let mut found = 0;
+    let mut pos = 0;
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        if let Some(needle) = needles.iter().find(|needle| rest.starts_with(**needle)) {
+            if found == nth {
+                return pos..pos + needle.len();
+            }
+            found += 1;
+        }
+        let mut chars = rest.chars();
+        let skipped = match chars.next() {
+            Some('\\') => 1 + chars.next().map_or(0, char::len_utf8),
+            c => c.map_or(1, char::len_utf8),
+        };
+        pos += skipped;
     }
-    iter.as_str()
+    0..pattern.len()
 }
 
 // ------------------------------------------------------------------------------//
@@ -511,11 +2044,9 @@ fn test_parsing_digit_class() {
     let s = r"\d"; // raw string so the backslash is preserved
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::CharClass(CharClass::Digit)],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::CharClass(CharClass::Digit)],
+    }]);
     assert_eq!(parsed, expected);
 }
 
@@ -523,11 +2054,9 @@ fn test_parsing_digit_class() {
 fn test_parsing_identifier_class() {
     let s = r"\w"; // raw string so the backslash is preserved
     let parsed: Pattern = s.parse().unwrap();
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::CharClass(CharClass::Identifier)],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::CharClass(CharClass::Identifier)],
+    }]);
     assert_eq!(parsed, expected);
 }
 
@@ -536,15 +2065,13 @@ fn test_parsing_literals() {
     let s = "abc";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::Literal('c'),
-            ],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::Literal('c'),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -554,15 +2081,13 @@ fn test_parsing_one_class() {
     let s = "[abc]";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::GroupClass(GroupClass::MatchOne(vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::Literal('c'),
-            ]))],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::GroupClass(GroupClass::MatchOne(vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::Literal('c'),
+        ]))],
+    }]);
     assert_eq!(parsed, expected);
 }
 #[test]
@@ -570,17 +2095,16 @@ fn test_parsing_sol() {
     let s = "^abc\\d\\w";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::SOL(vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::Literal('c'),
-                Token::CharClass(CharClass::Digit),
-                Token::CharClass(CharClass::Identifier),
-            ])],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Assertion(Assertion::StartOfLine),
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::Literal('c'),
+            Token::CharClass(CharClass::Digit),
+            Token::CharClass(CharClass::Identifier),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -589,17 +2113,16 @@ fn test_parsing_eol() {
     let s = "abc\\d\\w$";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::EOL(vec![
-                Token::CharClass(CharClass::Identifier),
-                Token::CharClass(CharClass::Digit),
-                Token::Literal('c'),
-                Token::Literal('b'),
-                Token::Literal('a'),
-            ])],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::Literal('c'),
+            Token::CharClass(CharClass::Digit),
+            Token::CharClass(CharClass::Identifier),
+            Token::Assertion(Assertion::EndOfLine),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -608,19 +2131,17 @@ fn test_parsing_combination() {
     let s = r"a\d[bc]\w";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![
-                Token::Literal('a'),
-                Token::CharClass(CharClass::Digit),
-                Token::GroupClass(GroupClass::MatchOne(vec![
-                    Token::Literal('b'),
-                    Token::Literal('c'),
-                ])),
-                Token::CharClass(CharClass::Identifier),
-            ],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::CharClass(CharClass::Digit),
+            Token::GroupClass(GroupClass::MatchOne(vec![
+                Token::Literal('b'),
+                Token::Literal('c'),
+            ])),
+            Token::CharClass(CharClass::Identifier),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -628,15 +2149,13 @@ fn test_parsing_combination() {
 fn test_parsing_none_class() {
     let s = "[^abc]";
     let parsed: Pattern = s.parse().unwrap();
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![Token::GroupClass(GroupClass::MatchNone(vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::Literal('c'),
-            ]))],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::GroupClass(GroupClass::MatchNone(vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::Literal('c'),
+        ]))],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -644,17 +2163,15 @@ fn test_parsing_none_class() {
 fn test_parsing_one_or_more() {
     let s = "abc+\\w\\d";
     let parsed: Pattern = s.parse().unwrap();
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::OneORMore(Box::new(Token::Literal('c'))),
-                Token::CharClass(CharClass::Identifier),
-                Token::CharClass(CharClass::Digit),
-            ],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::OneORMore(Box::new(Token::Literal('c'))),
+            Token::CharClass(CharClass::Identifier),
+            Token::CharClass(CharClass::Digit),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -663,17 +2180,15 @@ fn test_parsing_one_or_none() {
     let s = "abc?\\w\\d";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
-            tokens: vec![
-                Token::Literal('a'),
-                Token::Literal('b'),
-                Token::OneOrNone(Box::new(Token::Literal('c'))),
-                Token::CharClass(CharClass::Identifier),
-                Token::CharClass(CharClass::Digit),
-            ],
-        }],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::Literal('b'),
+            Token::OneOrNone(Box::new(Token::Literal('c'))),
+            Token::CharClass(CharClass::Identifier),
+            Token::CharClass(CharClass::Digit),
+        ],
+    }]);
 
     assert_eq!(parsed, expected);
 }
@@ -683,24 +2198,30 @@ fn test_parsing_alternation() {
     let s = "(cat|dog)";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![
-            SubPattern {
-                tokens: vec![
-                    Token::Literal('c'),
-                    Token::Literal('a'),
-                    Token::Literal('t'),
-                ],
-            },
-            SubPattern {
-                tokens: vec![
-                    Token::Literal('d'),
-                    Token::Literal('o'),
-                    Token::Literal('g'),
-                ],
-            },
-        ],
-    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::Group(Group {
+            capture: Some(CaptureGroup {
+                index: 1,
+                name: None,
+            }),
+            alternatives: vec![
+                SubPattern {
+                    tokens: vec![
+                        Token::Literal('c'),
+                        Token::Literal('a'),
+                        Token::Literal('t'),
+                    ],
+                },
+                SubPattern {
+                    tokens: vec![
+                        Token::Literal('d'),
+                        Token::Literal('o'),
+                        Token::Literal('g'),
+                    ],
+                },
+            ],
+        })],
+    }]);
     assert_eq!(parsed, expected);
 }
 
@@ -709,14 +2230,833 @@ fn test_parsing_one_or_more_digit() {
     let s = "a\\d+c";
     let parsed: Pattern = s.parse().unwrap();
 
-    let expected = Pattern {
-        sub_patterns: vec![SubPattern {
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::OneORMore(Box::new(Token::CharClass(CharClass::Digit))),
+            Token::Literal('c'),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_class_ranges_and_escapes() {
+    let s = r"[a-z0-9\]\-_]+";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::OneORMore(Box::new(Token::GroupClass(
+            GroupClass::MatchOne(vec![
+                Token::Range('a', 'z'),
+                Token::Range('0', '9'),
+                Token::Literal(']'),
+                Token::Literal('-'),
+                Token::Literal('_'),
+            ]),
+        )))],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_leading_bracket_in_class() {
+    // the ']' right after '[' is a literal, and it can start a range like any other char
+    let parsed: Pattern = "[]-a]".parse().unwrap();
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(
+        tokens[0],
+        Token::GroupClass(GroupClass::MatchOne(vec![Token::Range(']', 'a')]))
+    );
+    assert!(parsed.matches("^"));
+    assert!(!parsed.matches("-"));
+    let parsed: Pattern = "[^]a]".parse().unwrap();
+    assert!(!parsed.matches("]"));
+    assert!(parsed.matches("b"));
+}
+
+#[test]
+fn test_parsing_posix_class() {
+    let s = "[^[:alpha:][:space:]]";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::GroupClass(GroupClass::MatchNone(vec![
+            Token::CharClass(CharClass::Alpha),
+            Token::CharClass(CharClass::AsciiSpace),
+        ]))],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_invalid_classes() {
+    assert!(matches!(
+        "[z-a]".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::InvalidRange,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "[[:foo:]]".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnknownClass,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "[abc".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnclosedClass,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_nested_groups() {
+    let s = "((a|b)c|d)+";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![Token::OneORMore(Box::new(Token::Group(Group {
+            capture: Some(CaptureGroup {
+                index: 1,
+                name: None,
+            }),
+            alternatives: vec![
+                SubPattern {
+                    tokens: vec![
+                        Token::Group(Group {
+                            capture: Some(CaptureGroup {
+                                index: 2,
+                                name: None,
+                            }),
+                            alternatives: vec![
+                                SubPattern {
+                                    tokens: vec![Token::Literal('a')],
+                                },
+                                SubPattern {
+                                    tokens: vec![Token::Literal('b')],
+                                },
+                            ],
+                        }),
+                        Token::Literal('c'),
+                    ],
+                },
+                SubPattern {
+                    tokens: vec![Token::Literal('d')],
+                },
+            ],
+        })))],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_top_level_alternation_with_anchors() {
+    let s = "^a|b$";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![
+        SubPattern {
             tokens: vec![
+                Token::Assertion(Assertion::StartOfLine),
                 Token::Literal('a'),
-                Token::OneORMore(Box::new(Token::CharClass(CharClass::Digit))),
-                Token::Literal('c'),
             ],
-        }],
+        },
+        SubPattern {
+            tokens: vec![Token::Literal('b'), Token::Assertion(Assertion::EndOfLine)],
+        },
+    ]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_many_groups_stays_linear() {
+    // 30 groups of 2 alternatives used to expand into 2^30 strings
+    let s = "(a|b)".repeat(30);
+    let parsed: Pattern = s.parse().unwrap();
+
+    assert_eq!(parsed.sub_patterns.len(), 1);
+    assert_eq!(parsed.sub_patterns[0].tokens.len(), 30);
+}
+
+#[test]
+fn test_parsing_unbalanced_groups() {
+    assert!(matches!(
+        "(ab".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnclosedGroup,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "((a|b)".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnclosedGroup,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "ab)".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnmatchedParen,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_named_and_non_capturing_groups() {
+    let s = "(?:a)(?<word>b)(c)";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Group(Group {
+                capture: None,
+                alternatives: vec![SubPattern {
+                    tokens: vec![Token::Literal('a')],
+                }],
+            }),
+            Token::Group(Group {
+                capture: Some(CaptureGroup {
+                    index: 1,
+                    name: Some("word".to_string()),
+                }),
+                alternatives: vec![SubPattern {
+                    tokens: vec![Token::Literal('b')],
+                }],
+            }),
+            Token::Group(Group {
+                capture: Some(CaptureGroup {
+                    index: 2,
+                    name: None,
+                }),
+                alternatives: vec![SubPattern {
+                    tokens: vec![Token::Literal('c')],
+                }],
+            }),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+    assert_eq!(parsed.group_names(), vec![Some("word".to_string()), None]);
+}
+
+#[test]
+fn test_parsing_invalid_group_names() {
+    assert!(matches!(
+        "(?<1st>a)".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::InvalidGroupName,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "(?<x>a)(?<x>b)".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::DuplicateGroupName,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "(?<x a)".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::Unterminated,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_back_references() {
+    let s = r"(?<tag>\w)(x)\k<tag>\2";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens[2], Token::BackReference(1));
+    assert_eq!(tokens[3], Token::BackReference(2));
+}
+
+#[test]
+fn test_parsing_back_references_to_missing_groups() {
+    assert!(matches!(
+        r"(a)\2".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UndefinedGroup,
+            ..
+        })
+    ));
+    assert!(matches!(
+        r"\1".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UndefinedGroup,
+            ..
+        })
+    ));
+    assert!(matches!(
+        r"(?<a>x)\k<b>".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UndefinedGroup,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_star_and_counted_repetition() {
+    let s = r"a*[bc]{2}(de){1,}\d{2,4}";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens[0], Token::ZeroOrMore(Box::new(Token::Literal('a'))));
+    assert!(
+        matches!(&tokens[1], Token::Repeat(token, 2, Some(2)) if matches!(**token, Token::GroupClass(_)))
+    );
+    assert!(
+        matches!(&tokens[2], Token::Repeat(token, 1, None) if matches!(**token, Token::Group(_)))
+    );
+    assert_eq!(
+        tokens[3],
+        Token::Repeat(Box::new(Token::CharClass(CharClass::Digit)), 2, Some(4))
+    );
+}
+
+#[test]
+fn test_parsing_brace_that_is_not_a_repetition() {
+    let s = "a{b}";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('a'),
+            Token::Literal('{'),
+            Token::Literal('b'),
+            Token::Literal('}'),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_repetition_limits() {
+    assert!(matches!(
+        "a{1000000}".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::InvalidRepetition,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "a{2,1001}".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::InvalidRepetition,
+            ..
+        })
+    ));
+    assert!(matches!(
+        "a{3,2}".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::InvalidRepetition,
+            ..
+        })
+    ));
+    assert!("a{1000}".parse::<Pattern>().is_ok());
+}
+
+#[test]
+fn test_parsing_stacked_quantifiers() {
+    // a quantifier after a quantifier repeats it again instead of being a literal
+    let a = || Box::new(Token::Literal('a'));
+    let parsed: Pattern = "a**".parse().unwrap();
+    assert_eq!(
+        parsed.sub_patterns[0].tokens,
+        [Token::ZeroOrMore(Box::new(Token::ZeroOrMore(a())))]
+    );
+    assert!(parsed.matches("a"));
+    assert_eq!(parsed.find("aa*").unwrap().range(), 0..2);
+    let parsed: Pattern = "^a+*$".parse().unwrap();
+    assert!(parsed.matches(""));
+    assert!(parsed.matches("aaa"));
+    assert!(!parsed.matches("a+"));
+    let parsed: Pattern = "^a{2}{3}$".parse().unwrap();
+    assert_eq!(
+        parsed.sub_patterns[0].tokens[1],
+        Token::Repeat(Box::new(Token::Repeat(a(), 2, Some(2))), 3, Some(3))
+    );
+    assert!(parsed.matches("aaaaaa"));
+    assert!(!parsed.matches("aa{3}"));
+    // the ? and + right after a quantifier still make it lazy and possessive
+    let parsed: Pattern = "a*?+".parse().unwrap();
+    assert_eq!(
+        parsed.sub_patterns[0].tokens,
+        [Token::OneORMore(Box::new(Token::Lazy(Box::new(
+            Token::ZeroOrMore(a())
+        ))))]
+    );
+
+    // zero-width tokens have nothing to repeat
+    for (pattern, span) in [
+        ("(?=a)*", 5..6),
+        (r"x\b+", 3..4),
+        ("^*", 1..2),
+        ("a$?", 2..3),
+    ] {
+        let error = pattern.parse::<Pattern>().unwrap_err();
+        assert_eq!(
+            (error.kind, error.span),
+            (ParseErrorKind::InvalidRepetition, span),
+            "{}",
+            pattern
+        );
+    }
+    let error = r"\b{2}".parse::<Pattern>().unwrap_err();
+    assert_eq!(error.span, 2..5);
+    assert_eq!(
+        error.suggestion.as_deref(),
+        Some(r"escape it as \{ to match a literal {")
+    );
+    // a { that isn't a count is still a literal
+    assert!(r"a\b{x}".parse::<Pattern>().unwrap().matches("a{x}"));
+}
+
+#[test]
+fn test_parsing_lazy_and_possessive_quantifiers() {
+    let s = "a+?b*+c{2,3}?(?>d)";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(
+        tokens[0],
+        Token::Lazy(Box::new(Token::OneORMore(Box::new(Token::Literal('a')))))
+    );
+    assert_eq!(
+        tokens[1],
+        Token::Atomic(Box::new(Token::ZeroOrMore(Box::new(Token::Literal('b')))))
+    );
+    assert_eq!(
+        tokens[2],
+        Token::Lazy(Box::new(Token::Repeat(
+            Box::new(Token::Literal('c')),
+            2,
+            Some(3)
+        )))
+    );
+    assert_eq!(
+        tokens[3],
+        Token::Atomic(Box::new(Token::Group(Group {
+            capture: None,
+            alternatives: vec![SubPattern {
+                tokens: vec![Token::Literal('d')],
+            }],
+        })))
+    );
+    assert_eq!(parsed.group_count(), 0);
+}
+
+#[test]
+fn test_parsing_assertions_anywhere() {
+    let s = r"\bfoo\B|\A(^#|;$)\z";
+    let parsed: Pattern = s.parse().unwrap();
+
+    let first = &parsed.sub_patterns[0].tokens;
+    assert_eq!(first[0], Token::Assertion(Assertion::WordBoundary));
+    assert_eq!(first[4], Token::Assertion(Assertion::NotWordBoundary));
+
+    let second = &parsed.sub_patterns[1].tokens;
+    assert_eq!(second[0], Token::Assertion(Assertion::StartOfText));
+    assert_eq!(second[2], Token::Assertion(Assertion::EndOfText));
+    let Token::Group(group) = &second[1] else {
+        panic!("expected a group, got {:?}", second[1]);
+    };
+    assert_eq!(
+        group.alternatives[0].tokens[0],
+        Token::Assertion(Assertion::StartOfLine)
+    );
+    assert_eq!(
+        group.alternatives[1].tokens[1],
+        Token::Assertion(Assertion::EndOfLine)
+    );
+}
+
+#[test]
+fn test_parsing_class_escapes() {
+    // \d \w \s and their upper case negations, outside and inside [ ]
+    let parsed: Pattern = r"\d\D\w\W\s\S[\s\D]".parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::CharClass(CharClass::Digit),
+            Token::CharClass(CharClass::Not(Box::new(CharClass::Digit))),
+            Token::CharClass(CharClass::Identifier),
+            Token::CharClass(CharClass::Not(Box::new(CharClass::Identifier))),
+            Token::CharClass(CharClass::Space),
+            Token::CharClass(CharClass::Not(Box::new(CharClass::Space))),
+            Token::GroupClass(GroupClass::MatchOne(vec![
+                Token::CharClass(CharClass::Space),
+                Token::CharClass(CharClass::Not(Box::new(CharClass::Digit))),
+            ])),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_control_escapes() {
+    let parsed: Pattern = r"\t\n\r\f\v\a\e".parse().unwrap();
+
+    let expected: Vec<Token> = "\t\n\r\x0c\x0b\x07\x1b"
+        .chars()
+        .map(Token::Literal)
+        .collect();
+    assert_eq!(parsed.sub_patterns[0].tokens, expected);
+}
+
+#[test]
+fn test_parsing_code_point_escapes() {
+    // \xHH takes exactly two digits, the braced forms take one to six
+    let parsed: Pattern = r"\x41\x{3bb}\u{1F600}\x410[\x30-\x39]".parse().unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Literal('A'),
+            Token::Literal('λ'),
+            Token::Literal('😀'),
+            Token::Literal('A'),
+            Token::Literal('0'),
+            Token::GroupClass(GroupClass::MatchOne(vec![Token::Range('0', '9')])),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+
+    for invalid in [
+        r"\x4",
+        r"\xZZ",
+        r"\x{}",
+        r"\u{1234567}",
+        r"\u{D800}",
+        r"\u41",
+    ] {
+        assert!(
+            matches!(
+                invalid.parse::<Pattern>(),
+                Err(ParseError {
+                    kind: ParseErrorKind::InvalidEscape,
+                    ..
+                })
+            ),
+            "{} should be rejected",
+            invalid
+        );
+    }
+    assert!(matches!(
+        r"\x{41".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::Unterminated,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_escaped_metacharacters() {
+    // every metacharacter escaped is itself, and the unescaped '.' is the wildcard
+    let metacharacters = r".^$|()[]{}*+?\/-";
+    let escaped: String = metacharacters.chars().map(|c| format!("\\{}", c)).collect();
+    let parsed: Pattern = escaped.parse().unwrap();
+
+    let expected: Vec<Token> = metacharacters.chars().map(Token::Literal).collect();
+    assert_eq!(parsed.sub_patterns[0].tokens, expected);
+
+    let parsed: Pattern = r"a.\.".parse().unwrap();
+    assert_eq!(
+        parsed.sub_patterns[0].tokens,
+        vec![Token::Literal('a'), Token::WildCard, Token::Literal('.')]
+    );
+}
+
+#[test]
+fn test_parsing_invalid_escapes() {
+    for invalid in [r"\q", r"\y", r"[\q]", r"\é"] {
+        assert!(
+            matches!(
+                invalid.parse::<Pattern>(),
+                Err(ParseError {
+                    kind: ParseErrorKind::InvalidEscape,
+                    ..
+                })
+            ),
+            "{} should be rejected",
+            invalid
+        );
+    }
+    assert!(matches!(
+        r"ab\".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::TrailingBackslash,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_unicode_properties() {
+    let parsed: Pattern = r"\p{Greek}\PL[\p{Lu}]".parse().unwrap();
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens.len(), 3);
+    assert!(matches!(&tokens[0], Token::CharClass(CharClass::Property(p)) if p.name == "Greek"));
+    assert!(matches!(
+        &tokens[1],
+        Token::CharClass(CharClass::Not(class)) if matches!(**class, CharClass::Property(_))
+    ));
+
+    assert!(matches!(
+        r"\p{Klingon}".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnknownClass,
+            ..
+        })
+    ));
+    assert!(matches!(
+        r"\p{Greek".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::Unterminated,
+            ..
+        })
+    ));
+    assert!(matches!(
+        r"\p".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::Unterminated,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_ascii_only_option() {
+    let options = PatternOptions {
+        ascii_only: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options(r"\b[\d\W]\s\p{L}", &options).unwrap();
+
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::Assertion(Assertion::AsciiWordBoundary),
+            Token::GroupClass(GroupClass::MatchOne(vec![
+                Token::CharClass(CharClass::AsciiDigit),
+                Token::CharClass(CharClass::Not(Box::new(CharClass::AsciiIdentifier))),
+            ])),
+            Token::CharClass(CharClass::AsciiSpace),
+            Token::CharClass(CharClass::Property(unicode::property("L").unwrap())),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parsing_inline_flags() {
+    let parsed: Pattern = "(?i)a1(?-i:b|c)".parse().unwrap();
+
+    let case_insensitive = |on| Flags {
+        case_insensitive: Some(on),
+        ..Default::default()
+    };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::SetFlags(case_insensitive(true)),
+            Token::CaseInsensitive(Box::new(Token::Literal('a'))),
+            Token::Literal('1'),
+            Token::Group(Group {
+                alternatives: vec![
+                    SubPattern {
+                        tokens: vec![
+                            Token::SetFlags(case_insensitive(false)),
+                            Token::Literal('b'),
+                        ],
+                    },
+                    SubPattern {
+                        tokens: vec![Token::Literal('c')],
+                    },
+                ],
+                capture: None,
+            }),
+        ],
+    }]);
+    assert_eq!(parsed, expected);
+
+    for invalid in ["(?x)a", "(?-)a", "(?i-)a", "(?i"] {
+        assert!(
+            invalid.parse::<Pattern>().is_err(),
+            "{} should be rejected",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_parsing_smart_case() {
+    let options = PatternOptions {
+        smart_case: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options(r"[a-z]+\W", &options).unwrap();
+    assert!(matches!(
+        parsed.sub_patterns[0].tokens[0],
+        Token::OneORMore(ref token) if matches!(**token, Token::CaseInsensitive(_))
+    ));
+
+    let parsed = Pattern::with_options(r"[A-Z]+\W", &options).unwrap();
+    assert!(matches!(
+        parsed.sub_patterns[0].tokens[0],
+        Token::OneORMore(ref token) if matches!(**token, Token::GroupClass(_))
+    ));
+}
+
+#[test]
+fn test_parsing_look_arounds() {
+    let parsed: Pattern = r"(?<!x)a(?=b|cd)".parse().unwrap();
+
+    let literals = |s: &str| SubPattern {
+        tokens: s.chars().map(Token::Literal).collect(),
     };
+    let expected = Pattern::new(vec![SubPattern {
+        tokens: vec![
+            Token::LookAround(LookAround {
+                alternatives: vec![literals("x")],
+                direction: LookDirection::Behind { max_len: 1 },
+                negated: true,
+            }),
+            Token::Literal('a'),
+            Token::LookAround(LookAround {
+                alternatives: vec![literals("b"), literals("cd")],
+                direction: LookDirection::Ahead,
+                negated: false,
+            }),
+        ],
+    }]);
     assert_eq!(parsed, expected);
+
+    let parsed: Pattern = r"(?<=\$\d{1,3}|USD )".parse().unwrap();
+    assert!(matches!(
+        &parsed.sub_patterns[0].tokens[0],
+        Token::LookAround(LookAround {
+            direction: LookDirection::Behind { max_len: 4 },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parsing_unbounded_look_behind() {
+    for invalid in [r"(?<=a+)b", r"(?<=a*)b", r"(?<=(a)\1)b", r"(?<=a{300})b"] {
+        assert!(
+            matches!(
+                invalid.parse::<Pattern>(),
+                Err(ParseError {
+                    kind: ParseErrorKind::LookBehindTooLong,
+                    ..
+                })
+            ),
+            "{} should be rejected",
+            invalid
+        );
+    }
+    assert!(matches!(
+        "(?=ab".parse::<Pattern>(),
+        Err(ParseError {
+            kind: ParseErrorKind::UnclosedGroup,
+            ..
+        })
+    ));
+    // a lookahead has no such limit
+    assert!(r"a(?=b+)".parse::<Pattern>().is_ok());
+}
+
+#[test]
+fn test_parsing_wildcard_and_dot_all_flag() {
+    let parsed: Pattern = r".[.]\.(?s:.)".parse().unwrap();
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens[0], Token::WildCard);
+    assert_eq!(
+        tokens[1],
+        Token::GroupClass(GroupClass::MatchOne(vec![Token::Literal('.')]))
+    );
+    assert_eq!(tokens[2], Token::Literal('.'));
+    assert_eq!(tokens[3].children()[0][1], Token::AnyChar);
+
+    let options = PatternOptions {
+        dot_matches_new_line: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options(".(?-s).", &options).unwrap();
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens[0], Token::AnyChar);
+    assert_eq!(tokens[2], Token::WildCard);
+}
+
+#[test]
+fn test_multi_line_flag() {
+    let text = "fn main() {\n    unsafe {\n}";
+    let pattern: Pattern = r"^ +unsafe".parse().unwrap();
+    assert!(!pattern.matches(text));
+    let pattern: Pattern = r"(?m)^ +unsafe \{$".parse().unwrap();
+    assert_eq!(pattern.find(text).unwrap().range(), 12..24);
+    // a match can go over the \n, . only with (?s)
+    let pattern: Pattern = r"\(\) \{\n +unsafe".parse().unwrap();
+    assert!(pattern.matches(text));
+    let pattern: Pattern = r"main.*unsafe".parse().unwrap();
+    assert!(!pattern.matches(text));
+    let pattern: Pattern = r"(?s)main.*unsafe".parse().unwrap();
+    assert!(pattern.matches(text));
+
+    let options = PatternOptions {
+        multi_line: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options("^a$(?-m)$", &options).unwrap();
+    let tokens = &parsed.sub_patterns[0].tokens;
+    assert_eq!(tokens[0], Token::Assertion(Assertion::MultiLineStart));
+    assert_eq!(tokens[2], Token::Assertion(Assertion::MultiLineEnd));
+    assert_eq!(tokens[4], Token::Assertion(Assertion::EndOfLine));
+    let parsed = Pattern::with_options("^a$", &options).unwrap();
+    assert_eq!(parsed.find("b\na\nc").unwrap().range(), 2..3);
+}
+
+#[test]
+fn test_empty_patterns_and_alternatives() {
+    // the empty pattern matches every line, at its start
+    let pattern: Pattern = "".parse().unwrap();
+    assert!(pattern.sub_patterns[0].tokens.is_empty());
+    assert!(pattern.matches(""));
+    assert_eq!(pattern.find("abc").unwrap().range(), 0..0);
+    let pattern: Pattern = "^$".parse().unwrap();
+    assert!(pattern.matches(""));
+    assert!(!pattern.matches("a"));
+
+    // an empty alternative or group matches the empty string
+    let pattern: Pattern = "x(|b)y".parse().unwrap();
+    assert_eq!(
+        pattern.captures("xy").unwrap().get(1).unwrap().range(),
+        1..1
+    );
+    assert_eq!(
+        pattern.captures("xby").unwrap().get(1).unwrap().range(),
+        1..2
+    );
+    let pattern: Pattern = "a||b|".parse().unwrap();
+    assert_eq!(pattern.find("zb").unwrap().range(), 0..0);
+    let pattern: Pattern = r"a()(?:)b\1".parse().unwrap();
+    assert_eq!(pattern.find("xab").unwrap().range(), 1..3);
+    // the backtracker too, and a repeated empty group doesn't loop
+    let pattern: Pattern = r"(a|)\1b".parse().unwrap();
+    assert_eq!(pattern.find("b").unwrap().range(), 0..1);
+    assert_eq!(pattern.find("aab").unwrap().range(), 0..3);
+    let pattern: Pattern = "(|a)+$".parse().unwrap();
+    assert_eq!(pattern.find("aa").unwrap().range(), 0..2);
+    let pattern: Pattern = "()*b".parse().unwrap();
+    assert_eq!(pattern.find("ab").unwrap().range(), 1..2);
 }
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+// ------------------------------------------------------------------------------//
+//                                  Aho-Corasick                                 //
+// ------------------------------------------------------------------------------//
+
+// finds any of many literal strings in one pass over the input, whatever their number
+// the literals are put in a trie of their bytes; when the next byte has no edge, the
+// failure link jumps to the longest suffix of what was read that is still in the trie,
+// so no byte of the input is read twice
+#[derive(Debug)]
+pub struct AhoCorasick {
+    states: Vec<State>,
+    // the root has an edge for every byte, it is the state the search spends the most time in
+    root: Box<[u32; 256]>,
+    ascii_case_insensitive: bool,
+    literal_count: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    edges: Vec<(u8, u32)>, // sorted by byte
+    fail: u32,
+    depth: usize,
+    // the longest literal ending here, also through the failure links: its index and length
+    output: Option<(usize, usize)>,
+    // the index of every literal spelled by the path to this state, the same literal can be
+    // in the list more than once
+    literals: Vec<usize>,
+}
+
+const ROOT: u32 = 0;
+
+impl AhoCorasick {
+    pub fn new<L: AsRef<[u8]>>(literals: &[L]) -> Self {
+        Self::build(literals, false)
+    }
+
+    // 'A' and 'a' are the same byte, other chars still have to be written exactly
+    pub fn ascii_case_insensitive<L: AsRef<[u8]>>(literals: &[L]) -> Self {
+        Self::build(literals, true)
+    }
+
+    fn build<L: AsRef<[u8]>>(literals: &[L], ascii_case_insensitive: bool) -> Self {
+        let mut automaton = AhoCorasick {
+            states: vec![State::default()],
+            root: Box::new([ROOT; 256]),
+            ascii_case_insensitive,
+            literal_count: literals.len(),
+        };
+        for (index, literal) in literals.iter().enumerate() {
+            let mut state = ROOT;
+            for &byte in literal.as_ref() {
+                let byte = automaton.fold(byte);
+                state = match automaton.edge(state, byte) {
+                    Some(next) => next,
+                    None => automaton.add_edge(state, byte),
+                };
+            }
+            // find reports the first of the same literal given twice
+            let state = &mut automaton.states[state as usize];
+            state.output.get_or_insert((index, state.depth));
+            state.literals.push(index);
+        }
+        automaton.link_failures();
+        automaton
+    }
+
+    fn add_edge(&mut self, state: u32, byte: u8) -> u32 {
+        let next = self.states.len() as u32;
+        let depth = self.states[state as usize].depth + 1;
+        self.states.push(State {
+            depth,
+            ..State::default()
+        });
+        let edges = &mut self.states[state as usize].edges;
+        let at = edges.partition_point(|(b, _)| *b < byte);
+        edges.insert(at, (byte, next));
+        if state == ROOT {
+            self.root[byte as usize] = next;
+        }
+        next
+    }
+
+    // breadth first, so the failure link of a state's parent is known before the state's
+    fn link_failures(&mut self) {
+        let mut queue: VecDeque<u32> = self.states[ROOT as usize]
+            .edges
+            .iter()
+            .map(|(_, child)| *child)
+            .collect();
+        while let Some(state) = queue.pop_front() {
+            let edges = self.states[state as usize].edges.clone();
+            for (byte, child) in edges {
+                let mut fail = self.states[state as usize].fail;
+                let fail = loop {
+                    if let Some(next) = self.edge(fail, byte) {
+                        break next;
+                    }
+                    if fail == ROOT {
+                        break ROOT;
+                    }
+                    fail = self.states[fail as usize].fail;
+                };
+                let inherited = self.states[fail as usize].output;
+                let child_state = &mut self.states[child as usize];
+                child_state.fail = fail;
+                child_state.output = child_state.output.or(inherited);
+                queue.push_back(child);
+            }
+        }
+    }
+
+    fn edge(&self, state: u32, byte: u8) -> Option<u32> {
+        if state == ROOT {
+            return Some(self.root[byte as usize]).filter(|next| *next != ROOT);
+        }
+        let edges = &self.states[state as usize].edges;
+        edges
+            .binary_search_by_key(&byte, |(b, _)| *b)
+            .ok()
+            .map(|at| edges[at].1)
+    }
+
+    fn fold(&self, byte: u8) -> u8 {
+        match self.ascii_case_insensitive {
+            true => byte.to_ascii_lowercase(),
+            false => byte,
+        }
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    // the index of every literal in the haystack, in increasing order
+    pub fn matching_literals(&self, haystack: &[u8]) -> Vec<usize> {
+        let mut found = vec![false; self.literal_count];
+        let mut state = ROOT;
+        let mut mark = |mut state: u32| loop {
+            // the literals ending here: the state's own, then the ones down its failure links
+            let current = &self.states[state as usize];
+            for index in &current.literals {
+                found[*index] = true;
+            }
+            if state == ROOT {
+                break;
+            }
+            state = current.fail;
+        };
+        mark(ROOT);
+        for &byte in haystack {
+            let byte = self.fold(byte);
+            state = loop {
+                if let Some(next) = self.edge(state, byte) {
+                    break next;
+                }
+                if state == ROOT {
+                    break ROOT;
+                }
+                state = self.states[state as usize].fail;
+            };
+            mark(state);
+        }
+        (0..self.literal_count)
+            .filter(|index| found[*index])
+            .collect()
+    }
+
+    // the leftmost literal in the haystack, the longest one when several start there,
+    // with the index it had in the list and its span
+    pub fn find(&self, haystack: &[u8]) -> Option<(usize, Range<usize>)> {
+        let mut found: Option<(usize, Range<usize>)> = None;
+        let mut state = ROOT;
+        let keep = |found: &mut Option<(usize, Range<usize>)>, state: &State, end: usize| {
+            let Some((index, len)) = state.output else {
+                return;
+            };
+            let better = match found {
+                Some((_, span)) => end - len <= span.start,
+                None => true,
+            };
+            if better {
+                *found = Some((index, end - len..end));
+            }
+        };
+        keep(&mut found, &self.states[ROOT as usize], 0);
+        for (offset, &byte) in haystack.iter().enumerate() {
+            let byte = self.fold(byte);
+            state = loop {
+                if let Some(next) = self.edge(state, byte) {
+                    break next;
+                }
+                if state == ROOT {
+                    break ROOT;
+                }
+                state = self.states[state as usize].fail;
+            };
+            let end = offset + 1;
+            let current = &self.states[state as usize];
+            // what the trie is following now started after the match we have, it can only
+            // lead to matches on its right
+            if let Some((_, span)) = &found {
+                if end - current.depth > span.start {
+                    break;
+                }
+            }
+            keep(&mut found, current, end);
+        }
+        found
+    }
+}
+
+#[test]
+fn test_aho_corasick_finds_any_literal() {
+    let automaton = AhoCorasick::new(&["he", "she", "his", "hers"]);
+    assert_eq!(automaton.find(b"ushers"), Some((1, 1..4)));
+    assert_eq!(automaton.find(b"this"), Some((2, 1..4)));
+    assert_eq!(automaton.find(b"ahishers"), Some((2, 1..4)));
+    assert_eq!(automaton.find(b"hx"), None);
+
+    // a literal found through a failure link: "abcd" fails to "bc" after "abc"
+    let automaton = AhoCorasick::new(&["abcd", "bc"]);
+    assert_eq!(automaton.find(b"xabce"), Some((1, 2..4)));
+    // "bc" ends first, but "abcd" starts before it
+    assert_eq!(automaton.find(b"xabcd"), Some((0, 1..5)));
+    let automaton = AhoCorasick::new(&["b", "abc", "ab"]);
+    assert_eq!(automaton.find(b"abcd"), Some((1, 0..3)));
+
+    // metacharacters are plain bytes, and the empty literal is in every line
+    let automaton = AhoCorasick::new(&["a.b", "(x)"]);
+    assert!(automaton.is_match(b"1 (x) 2"));
+    assert!(!automaton.is_match(b"axb"));
+    assert!(AhoCorasick::new(&[""]).is_match(b""));
+}
+
+#[test]
+fn test_aho_corasick_reports_every_literal() {
+    let automaton = AhoCorasick::new(&["he", "she", "his", "hers", "x"]);
+    assert_eq!(automaton.matching_literals(b"ushers"), [0, 1, 3]);
+    assert_eq!(automaton.matching_literals(b"his"), [2]);
+    assert!(automaton.matching_literals(b"abc").is_empty());
+
+    // every copy of a literal given twice, also when they differ only by case
+    let automaton = AhoCorasick::new(&["abc", "bc", "abc"]);
+    assert_eq!(automaton.matching_literals(b"xabc"), [0, 1, 2]);
+    assert_eq!(automaton.find(b"xabc"), Some((0, 1..4)));
+    let automaton = AhoCorasick::ascii_case_insensitive(&["Error", "ERROR", "c"]);
+    assert_eq!(automaton.matching_literals(b"error"), [0, 1]);
+}
+
+#[test]
+fn test_aho_corasick_with_many_literals() {
+    let words: Vec<String> = (0..5000).map(|n| format!("word{}x", n * 7)).collect();
+    let automaton = AhoCorasick::new(&words);
+    assert_eq!(automaton.find(b"a word34993x here"), Some((4999, 2..12)));
+    assert!(!automaton.is_match(b"word34994x word1x"));
+}
+
+#[test]
+fn test_aho_corasick_ignoring_ascii_case() {
+    let automaton = AhoCorasick::ascii_case_insensitive(&["Error", "WARN"]);
+    assert_eq!(automaton.find(b"[warn] disk"), Some((1, 1..5)));
+    assert!(automaton.is_match(b"ERROR"));
+    assert!(!automaton.is_match(b"err"));
+}
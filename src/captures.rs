@@ -0,0 +1,226 @@
+use std::ops::Range;
+
+// one matched piece of the input, with its byte offsets
+// the input is a &str, or the raw bytes of a line for Pattern::find_bytes
+#[derive(Debug, PartialEq)]
+pub struct Match<'h, H: ?Sized = str> {
+    haystack: &'h H,
+    start: usize,
+    end: usize,
+}
+
+// derive would ask for H: Copy, which str and [u8] are not
+impl<H: ?Sized> Clone for Match<'_, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H: ?Sized> Copy for Match<'_, H> {}
+
+impl<'h, H: ?Sized> Match<'h, H> {
+    pub fn new(haystack: &'h H, start: usize, end: usize) -> Self {
+        Match {
+            haystack,
+            start,
+            end,
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+impl<'h> Match<'h> {
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.start..self.end]
+    }
+}
+
+impl<'h, H: AsRef<[u8]> + ?Sized> Match<'h, H> {
+    pub fn as_bytes(&self) -> &'h [u8] {
+        &self.haystack.as_ref()[self.start..self.end]
+    }
+}
+
+// the result of Pattern::captures
+// group 0 is the whole match, groups 1.. are the capture groups in the order of their '('
+#[derive(Debug, PartialEq)]
+pub struct Captures<'h, H: ?Sized = str> {
+    haystack: &'h H,
+    spans: Vec<Option<(usize, usize)>>,
+    names: Vec<Option<String>>, // names[i] is the name of group i + 1
+}
+
+impl<H: ?Sized> Clone for Captures<'_, H> {
+    fn clone(&self) -> Self {
+        Captures {
+            haystack: self.haystack,
+            spans: self.spans.clone(),
+            names: self.names.clone(),
+        }
+    }
+}
+
+impl<'h, H: ?Sized> Captures<'h, H> {
+    pub fn new(
+        haystack: &'h H,
+        spans: Vec<Option<(usize, usize)>>,
+        names: Vec<Option<String>>,
+    ) -> Self {
+        Captures {
+            haystack,
+            spans,
+            names,
+        }
+    }
+
+    // None when the group didn't take part in the match  ((a)|b on "b")
+    pub fn get(&self, index: usize) -> Option<Match<'h, H>> {
+        self.spans
+            .get(index)
+            .copied()
+            .flatten()
+            .map(|(start, end)| Match::new(self.haystack, start, end))
+    }
+
+    pub fn name(&self, name: &str) -> Option<Match<'h, H>> {
+        let index = self
+            .names
+            .iter()
+            .position(|group_name| group_name.as_deref() == Some(name))?;
+        self.get(index + 1)
+    }
+
+    // the number of groups including group 0
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h, H>>> + '_ {
+        (0..self.len()).map(|index| self.get(index))
+    }
+}
+
+#[cfg(test)]
+use crate::Pattern::{Pattern, PatternOptions};
+
+#[test]
+fn test_captures_spans() {
+    let pattern: Pattern = r"(\d+)-(\d+)".parse().unwrap();
+    let captures = pattern.captures("from 12-345 on").unwrap();
+
+    assert_eq!(captures.len(), 3);
+    assert_eq!(captures.get(0).unwrap().range(), 5..11);
+    assert_eq!(captures.get(1).unwrap().as_str(), "12");
+    assert_eq!(captures.get(2).unwrap().as_str(), "345");
+    assert_eq!(captures.get(3), None);
+}
+
+#[test]
+fn test_captures_named_groups() {
+    let pattern: Pattern = r"(?<key>\w+)=(?P<value>\w+)".parse().unwrap();
+    let captures = pattern.captures("set user=root").unwrap();
+
+    assert_eq!(captures.name("key").unwrap().as_str(), "user");
+    assert_eq!(captures.name("value").unwrap().as_str(), "root");
+    assert_eq!(captures.name("missing"), None);
+}
+
+#[test]
+fn test_captures_unmatched_and_repeated_groups() {
+    let pattern: Pattern = "(a)|(b)".parse().unwrap();
+    let captures = pattern.captures("xb").unwrap();
+    assert_eq!(captures.get(1), None);
+    assert_eq!(captures.get(2).unwrap().range(), 1..2);
+
+    // a repeated group keeps its last iteration, a failed branch leaves nothing behind
+    let pattern: Pattern = "(?:(a)|(b))+c".parse().unwrap();
+    let captures = pattern.captures("abac").unwrap();
+    assert_eq!(captures.get(0).unwrap().as_str(), "abac");
+    assert_eq!(captures.get(1).unwrap().range(), 2..3);
+    assert_eq!(captures.get(2).unwrap().range(), 1..2);
+}
+
+#[test]
+fn test_find_leftmost_match() {
+    let pattern: Pattern = "ca?t".parse().unwrap();
+    assert_eq!(pattern.find("the ct cat").unwrap().range(), 4..6);
+    assert!(pattern.find("dog").is_none());
+}
+
+#[test]
+fn test_find_with_lazy_quantifiers() {
+    let pattern: Pattern = "<.+?>".parse().unwrap();
+    assert_eq!(pattern.find("<a><b>").unwrap().as_str(), "<a>");
+
+    let pattern: Pattern = "x{2,4}?".parse().unwrap();
+    assert_eq!(pattern.find("xxxx").unwrap().as_str(), "xx");
+
+    let pattern: Pattern = "(a*?)(a+)".parse().unwrap();
+    let captures = pattern.captures("aaa").unwrap();
+    assert_eq!(captures.get(1).unwrap().as_str(), "");
+    assert_eq!(captures.get(2).unwrap().as_str(), "aaa");
+
+    // lazy still has to reach the rest of the pattern
+    let pattern: Pattern = "a??b".parse().unwrap();
+    assert_eq!(pattern.find("ab").unwrap().as_str(), "ab");
+}
+
+#[test]
+fn test_captures_on_bytes() {
+    // a Latin-1 line is not UTF-8, the spans are still byte offsets into it
+    let pattern: Pattern = r"user=(\w+) (.+)$".parse().unwrap();
+    let input = b"caf\xe9 user=root d\xe9j\xe0 vu";
+    let captures = pattern.captures_bytes(input).unwrap();
+    assert_eq!(captures.get(0).unwrap().range(), 5..22);
+    assert_eq!(captures.get(1).unwrap().as_bytes(), b"root");
+    assert_eq!(captures.get(2).unwrap().as_bytes(), b"d\xe9j\xe0 vu");
+    assert_eq!(pattern.find("user=x y").unwrap().as_bytes(), b"user=x y");
+}
+
+#[test]
+fn test_find_leftmost_longest() {
+    let longest = PatternOptions {
+        leftmost_longest: true,
+        ..Default::default()
+    };
+    // the first alternative that matches, or the longest match like POSIX tools report
+    let cases = [
+        ("sam|samwise", "samwise", "sam", "samwise"),
+        ("a+?", "aaa", "a", "aaa"),
+        ("(ab|a)(c|bcd)", "abcd", "abc", "abcd"),
+        // with a backreference the backtracker has to try every way too
+        (r"(a|ab)\1?b*", "ababb", "ab", "ababb"),
+    ];
+    for (pattern, input, first, longest_match) in cases {
+        let first_match: Pattern = pattern.parse().unwrap();
+        assert_eq!(first_match.find(input).unwrap().as_str(), first);
+        let pattern = Pattern::with_options(pattern, &longest).unwrap();
+        assert_eq!(pattern.find(input).unwrap().as_str(), longest_match);
+    }
+    // a later start never wins, however long
+    let pattern = Pattern::with_options("b|abc|bcdef", &longest).unwrap();
+    assert_eq!(pattern.find("xabcdef").unwrap().range(), 1..4);
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::nfa::{Inst, Program};
+use crate::unicode;
+use crate::utf8;
+use crate::Pattern::Token;
+
+// how many DFA states the cache keeps before it is cleared
+pub const MAX_CACHED_STATES: usize = 2000;
+
+// a search that has to clear the cache more often than this gives up, the Pike VM takes over
+pub const MAX_CACHE_CLEARS: usize = 3;
+
+// ------------------------------------------------------------------------------//
+//                                    Lazy DFA                                   //
+// ------------------------------------------------------------------------------//
+
+// answers "is there a match anywhere in the input" with one table lookup per char
+// its states are sets of NFA instructions, built from the Program the first time a search
+// needs them and kept in a bounded cache for the next searches
+pub struct LazyDfa {
+    // one cache for every thread searching with the pattern at the same time: a search takes
+    // one out and puts it back, so each thread keeps the states it built for its next lines
+    caches: Mutex<Vec<Cache>>,
+    max_states: usize,
+}
+
+impl LazyDfa {
+    pub fn new() -> Self {
+        Self::with_capacity(MAX_CACHED_STATES)
+    }
+
+    pub fn with_capacity(max_states: usize) -> Self {
+        LazyDfa {
+            caches: Mutex::new(vec![]),
+            max_states,
+        }
+    }
+
+    // is there a match starting at `start` or later
+    // None when the cache thrashed, the caller has to ask the Pike VM instead
+    pub fn is_match(&self, program: &Program, input: &[u8], start: usize) -> Option<bool> {
+        let cache = self.pool().pop();
+        let mut cache = cache.unwrap_or_else(|| Cache::new(self.max_states));
+        let found = cache.is_match(program, input, start);
+        self.pool().push(cache);
+        found
+    }
+
+    // a thread that panicked while holding the lock left the caches as they were
+    fn pool(&self) -> MutexGuard<'_, Vec<Cache>> {
+        self.caches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for LazyDfa {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the cache is not part of what a pattern is, only say how big it is
+impl fmt::Debug for LazyDfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LazyDfa(max_states: {})", self.max_states)
+    }
+}
+
+// what a DFA state is made of: the instructions the NFA threads wait on
+// and the kind of char they just consumed, for the assertions
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StateKey {
+    pcs: Vec<usize>,
+    before: Option<char>,
+}
+
+struct State {
+    key: StateKey,
+    // the next state for each char, MATCH when a match ends before that char
+    ascii: [Option<usize>; 128],
+    others: HashMap<char, usize>,
+    at_end: Option<bool>, // is there a match ending at the end of the input
+}
+
+const MATCH: usize = usize::MAX;
+
+struct Cache {
+    states: Vec<State>,
+    ids: HashMap<StateKey, usize>,
+    max_states: usize,
+}
+
+impl Cache {
+    fn new(max_states: usize) -> Self {
+        Cache {
+            states: vec![],
+            ids: HashMap::new(),
+            max_states,
+        }
+    }
+
+    fn is_match(&mut self, program: &Program, input: &[u8], start: usize) -> Option<bool> {
+        let mut clears = 0;
+        let mut current = self.state_id(StateKey {
+            pcs: vec![],
+            before: utf8::decode_last(&input[..start]).map(|(c, _)| look_behind_kind(c)),
+        });
+        for (c, _) in utf8::chars(&input[start..]) {
+            let next = self.next_state(program, current, c);
+            if next == MATCH {
+                return Some(true);
+            }
+            current = next;
+            if self.states.len() >= self.max_states {
+                clears += 1;
+                if clears > MAX_CACHE_CLEARS {
+                    return None;
+                }
+                let key = self.states[current].key.clone();
+                self.states.clear();
+                self.ids.clear();
+                current = self.state_id(key);
+            }
+        }
+        let state = &self.states[current];
+        if let Some(at_end) = state.at_end {
+            return Some(at_end);
+        }
+        let (_, matched) = closure(program, &state.key, None);
+        self.states[current].at_end = Some(matched);
+        Some(matched)
+    }
+
+    fn next_state(&mut self, program: &Program, current: usize, c: char) -> usize {
+        let state = &self.states[current];
+        let cached = match c.is_ascii() {
+            true => state.ascii[c as usize],
+            false => state.others.get(&c).copied(),
+        };
+        if let Some(next) = cached {
+            return next;
+        }
+        let (waiting, matched) = closure(program, &state.key, Some(c));
+        let next = if matched {
+            MATCH
+        } else {
+            let mut pcs: Vec<usize> = waiting
+                .into_iter()
+                .filter(|pc| match &program.insts[*pc] {
+                    Inst::Char(token) => token.match_char(c, false),
+                    _ => false,
+                })
+                .map(|pc| pc + 1)
+                .collect();
+            pcs.sort_unstable();
+            pcs.dedup();
+            self.state_id(StateKey {
+                pcs,
+                before: Some(look_behind_kind(c)),
+            })
+        };
+        let state = &mut self.states[current];
+        match c.is_ascii() {
+            true => state.ascii[c as usize] = Some(next),
+            false => {
+                state.others.insert(c, next);
+            }
+        }
+        next
+    }
+
+    fn state_id(&mut self, key: StateKey) -> usize {
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+        self.states.push(State {
+            key: key.clone(),
+            ascii: [None; 128],
+            others: HashMap::new(),
+            at_end: None,
+        });
+        self.ids.insert(key, self.states.len() - 1);
+        self.states.len() - 1
+    }
+}
+
+// the assertions only need to know if the char before is a word char (Unicode or ASCII)
+// or a \n, so chars of the same kind share their states
+fn look_behind_kind(c: char) -> char {
+    if c == '\n' {
+        '\n'
+    } else if c.is_ascii_alphanumeric() || c == '_' {
+        'a'
+    } else if unicode::is_word_char(c) {
+        'é'
+    } else {
+        ' '
+    }
+}
+
+// follow the Jump, Split, Save and Assert instructions from the state's instructions,
+// and from the start of the program since a match can start anywhere
+// gives the Char instructions reached and whether Match was reached before `after`
+fn closure(program: &Program, key: &StateKey, after: Option<char>) -> (Vec<usize>, bool) {
+    let mut seen = vec![false; program.insts.len()];
+    let mut stack: Vec<usize> = key.pcs.iter().rev().copied().collect();
+    stack.push(0);
+    let mut waiting = vec![];
+    let mut matched = false;
+    while let Some(pc) = stack.pop() {
+        if std::mem::replace(&mut seen[pc], true) {
+            continue;
+        }
+        match &program.insts[pc] {
+            Inst::Char(_) => waiting.push(pc),
+            Inst::Split(first, second) => {
+                stack.push(*second);
+                stack.push(*first);
+            }
+            Inst::Jump(target) => stack.push(*target),
+            Inst::Save(_) => stack.push(pc + 1),
+            Inst::Assert(assertion) => {
+                if Token::assertion_holds(assertion, key.before, after) {
+                    stack.push(pc + 1);
+                }
+            }
+            Inst::Match(_) => matched = true,
+            // the repeat is left or goes on, the same matches can be reached either way
+            Inst::ExitIfEmpty(_, exit) => {
+                stack.push(*exit);
+                stack.push(pc + 1);
+            }
+        }
+    }
+    (waiting, matched)
+}
+
+#[cfg(test)]
+use crate::Pattern::{MatchState, Pattern};
+
+#[cfg(test)]
+fn pike_vm_matches(pattern: &Pattern, program: &Program, input: &str) -> bool {
+    program.search(
+        &mut MatchState::new(input.as_bytes(), pattern.group_count()),
+        0,
+        false,
+    )
+}
+
+#[test]
+fn test_lazy_dfa_agrees_with_the_pike_vm() {
+    let cases = [
+        ("abc", ["xxabcxx", "ab", ""]),
+        ("^a.c$", ["abc", "a\nc", "abcd"]),
+        (r"\bfoo\b", ["a foo.", "food", "foo"]),
+        (r"\Bé\B", ["café", "aéa", "é"]),
+        ("(?i)straße|^$", ["STRASSE", "Straße", ""]),
+        (r"\d{2,3}-\w+?x", ["12-ax", "1-ax", "123-x"]),
+        ("x?$", ["anything", "", "x"]),
+    ];
+    for (pattern, inputs) in cases {
+        let pattern: Pattern = pattern.parse().unwrap();
+        let group_count = pattern.group_count();
+        let program = Program::compile(&pattern.sub_patterns, group_count).unwrap();
+        let dfa = LazyDfa::new();
+        for input in inputs {
+            assert_eq!(
+                dfa.is_match(&program, input.as_bytes(), 0),
+                Some(pike_vm_matches(&pattern, &program, input)),
+                "{:?} on {:?}",
+                pattern.sub_patterns,
+                input
+            );
+        }
+    }
+}
+
+#[test]
+fn test_lazy_dfa_gives_up_when_the_cache_thrashes() {
+    // the DFA for this needs a state for every combination of the last 12 chars
+    let pattern: Pattern = "a[ab]{12}c".parse().unwrap();
+    let program = Program::compile(&pattern.sub_patterns, 0).unwrap();
+    let mut seed = 0x2545_f491_u32;
+    let input: String = (0..20_000)
+        .map(|_| {
+            // xorshift, for a's and b's in no particular order
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            if seed & 1 == 1 {
+                'a'
+            } else {
+                'b'
+            }
+        })
+        .collect();
+
+    let dfa = LazyDfa::with_capacity(100);
+    assert_eq!(dfa.is_match(&program, input.as_bytes(), 0), None);
+    // the Pattern falls back to the Pike VM and still answers
+    assert!(!pattern.matches(&input));
+    assert!(pattern.matches(&format!("{}a{}c", input, "b".repeat(12))));
+
+    let dfa = LazyDfa::with_capacity(100_000);
+    assert_eq!(dfa.is_match(&program, input.as_bytes(), 0), Some(false));
+}
+
+#[test]
+fn test_lazy_dfa_keeps_a_cache_per_thread() {
+    let pattern: Pattern = r"\d+ms$".parse().unwrap();
+    let program = Program::compile(&pattern.sub_patterns, 0).unwrap();
+    let dfa = LazyDfa::new();
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for n in 0..200 {
+                    let line = format!("took {}ms", n);
+                    assert_eq!(dfa.is_match(&program, line.as_bytes(), 0), Some(true));
+                }
+            });
+        }
+    });
+    // the caches are kept with their states, never more than the threads that searched
+    let caches = dfa.pool();
+    assert!((1..=4).contains(&caches.len()));
+    assert!(caches.iter().all(|cache| !cache.states.is_empty()));
+}
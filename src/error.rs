@@ -0,0 +1,196 @@
+use std::ops::Range;
+
+use thiserror::Error;
+
+// ------------------------------------------------------------------------------//
+//                                  Parse Errors                                 //
+// ------------------------------------------------------------------------------//
+
+// what is wrong with a pattern, the kinds don't change between versions so callers
+// can match on them instead of on the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnclosedGroup,      // (ab
+    UnmatchedParen,     // ab)
+    UnclosedClass,      // [ab
+    Unterminated,       // \k<a  (?<a  \p{Greek  \x{41  [:alpha  (?i
+    TrailingBackslash,  // ab\
+    InvalidEscape,      // \q  \xZZ  \u{D800}
+    InvalidRepetition,  // a{3,2}  a{5000}
+    InvalidRange,       // [z-a]  [a-\d]
+    UnknownClass,       // [[:foo:]]  \p{Foo}
+    UnknownGroupType,   // (?#...)
+    InvalidFlags,       // (?q)  (?i-)
+    InvalidGroupName,   // (?<1a>...)
+    DuplicateGroupName, // (?<a>x)(?<a>y)
+    UndefinedGroup,     // (a)\2  \k<b>
+    LookBehindTooLong,  // (?<=a+)
+    // past one of the Limits
+    NestingTooDeep,      // ((((a))))  with nest_limit 3
+    PatternTooLarge,     // (a{1000}){1000}
+    TooManyAlternatives, // a|b|c  with alternation_limit 2
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message} at offset {}", .span.start)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    // the bytes of the pattern the error is about, empty at the end of the pattern
+    pub span: Range<usize>,
+    pub message: String,
+    // how the pattern could be written instead, when there is an obvious fix
+    pub suggestion: Option<String>,
+    // the pattern as it was given, so the error can be shown under it
+    pub pattern: String,
+}
+
+impl ParseError {
+    // the parser only sees what is left of the pattern: `from` is the rest of it where the
+    // problem starts and `to` the rest where it ends, `located` turns them into offsets
+    pub(crate) fn new(kind: ParseErrorKind, message: String, from: &str, to: &str) -> Self {
+        ParseError {
+            kind,
+            span: from.len()..to.len(),
+            message,
+            suggestion: None,
+            pattern: String::new(),
+        }
+    }
+
+    // for the errors found after parsing, when the span is already known
+    pub(crate) fn at(kind: ParseErrorKind, message: String, span: Range<usize>) -> Self {
+        ParseError {
+            kind,
+            span,
+            message,
+            suggestion: None,
+            pattern: String::new(),
+        }
+    }
+
+    pub(crate) fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    // turn the lengths of the rest of the pattern into offsets in `pattern`
+    pub(crate) fn located(mut self, pattern: &str) -> Self {
+        self.span = pattern.len() - self.span.start..pattern.len() - self.span.end;
+        self
+    }
+
+    // the pattern with a caret under the problem, and the suggestion when there is one
+    //   [z-a]
+    //    ^~~
+    pub fn diagnostic(&self) -> String {
+        let column = self.pattern[..self.span.start].chars().count();
+        let width = self.pattern[self.span.clone()].chars().count();
+        let mut diagnostic = format!(
+            "{}\n{}^{}",
+            self.pattern,
+            " ".repeat(column),
+            "~".repeat(width.saturating_sub(1))
+        );
+        if let Some(suggestion) = &self.suggestion {
+            diagnostic.push_str(&format!("\nhelp: {}", suggestion));
+        }
+        diagnostic
+    }
+}
+
+// a search that went past one of the Limits, it can't tell whether the input matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MatchError {
+    #[error("the search took more than {limit} steps")]
+    StepLimitExceeded { limit: usize },
+    #[error("the backtracker tried more than {limit} tokens")]
+    BacktrackLimitExceeded { limit: usize },
+    #[error("the search needed more than {limit} bytes of stack")]
+    StackLimitExceeded { limit: usize },
+}
+
+#[cfg(test)]
+use crate::Pattern::Pattern;
+
+#[test]
+fn test_parse_error_spans() {
+    let error = "ab[z-a]c".parse::<Pattern>().unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::InvalidRange);
+    assert_eq!(error.span, 3..6);
+    assert_eq!(error.pattern, "ab[z-a]c");
+    assert_eq!(error.suggestion.as_deref(), Some("write the range as a-z"));
+
+    let error = "x(ab|c".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::UnclosedGroup, 1..6)
+    );
+    let error = "ab)".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::UnmatchedParen, 2..3)
+    );
+    let error = "é\\q".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::InvalidEscape, 2..4)
+    );
+    let error = "ab\\".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::TrailingBackslash, 2..3)
+    );
+    // found once the whole pattern is parsed
+    let error = r"(a)\\2\2".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::UndefinedGroup, 6..8)
+    );
+    let error = "(?<n>a)(?<n>b)".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::DuplicateGroupName, 7..12)
+    );
+}
+
+#[test]
+fn test_parse_error_spans_in_basic_syntax() {
+    use crate::syntax::Syntax;
+    use crate::Pattern::PatternOptions;
+
+    let options = PatternOptions {
+        syntax: Syntax::Basic,
+        ..Default::default()
+    };
+    // the span is in the pattern as written, not in its extended translation
+    let error = Pattern::with_options(r"a\(b\{3,2\}", &options).unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::InvalidRepetition);
+    assert_eq!(error.span, 4..11);
+    let error = Pattern::with_options(r"x\)", &options).unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::UnmatchedParen, 1..3)
+    );
+    assert_eq!(
+        error.suggestion.as_deref(),
+        Some(r"remove the \ to match a literal )")
+    );
+}
+
+#[test]
+fn test_parse_error_diagnostic() {
+    let error = "naïve[z-a]".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        error.diagnostic(),
+        "naïve[z-a]\n      ^~~\nhelp: write the range as a-z"
+    );
+    assert_eq!(
+        error.to_string(),
+        "invalid range z-a, start is greater than end at offset 7"
+    );
+    let error = "a(".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        error.diagnostic(),
+        "a(\n ^\nhelp: add a ) to close the group"
+    );
+}
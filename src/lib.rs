@@ -0,0 +1,22 @@
+// the pattern engine, usable on its own:
+//
+//   let pattern: rusty_grep::Pattern::Pattern = r"(?<year>\d+)-(\d+)".parse()?;
+//   let captures = pattern.captures("released 2024-05").unwrap();
+//   assert_eq!(captures.name("year").unwrap().as_str(), "2024");
+
+#[allow(non_snake_case)]
+pub mod Pattern;
+pub mod aho_corasick;
+pub mod captures;
+pub mod dfa;
+pub mod error;
+pub mod limits;
+pub mod nfa;
+pub mod optimizer;
+pub mod pattern_matcher;
+pub mod pattern_set;
+pub mod prefilter;
+pub mod syntax;
+pub mod unicode;
+mod unicode_tables;
+pub mod utf8;
@@ -0,0 +1,154 @@
+// ------------------------------------------------------------------------------//
+//                                Resource Limits                                //
+// ------------------------------------------------------------------------------//
+
+// bounds on what a pattern may cost, for patterns that come from people we don't trust
+// (a search box): going past one is an error instead of a crash, a hang or all the memory
+// the ones checked by the parser are ParseErrors, the ones checked while matching MatchErrors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // how deep groups and lookarounds may be nested  ((((a))))  is 4
+    pub nest_limit: usize,
+    // how many instructions the compiled program may have, once every {n,m} is expanded
+    // (a{1000}){1000} would be a million
+    pub size_limit: usize,
+    // how many alternatives the whole pattern may have, counting the ones in every group
+    pub alternation_limit: usize,
+    // how many steps one search may take, None for no limit (the default)
+    // the backtracker counts every token it tries, the Pike VM (of a pattern or of a
+    // PatternSet) every thread it moves, the lazy DFA is linear and doesn't count
+    pub step_limit: Option<usize>,
+    // how many tokens one search of the backtracker may try, like PCRE's match_limit
+    // the backtracker can be exponential (^((a|aa)+)\1*$ on a line of a's and a !), so there
+    // is one by default, the Pike VM is linear and doesn't count against it
+    pub backtrack_limit: usize,
+    // how many bytes of stack the backtracker may use: it recurses about once for every
+    // token it has matched on the way to the current position, a long line could overflow
+    // the stack (the threads Rust spawns have 2 MiB)
+    pub stack_limit: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            nest_limit: 100,
+            size_limit: 100_000,
+            alternation_limit: 10_000,
+            step_limit: None,
+            backtrack_limit: 10_000_000,
+            stack_limit: 1 << 20,
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::Pattern::{MatchError, ParseErrorKind, Pattern, PatternOptions};
+
+#[cfg(test)]
+fn with_limits(pattern: &str, limits: Limits) -> Result<Pattern, crate::Pattern::ParseError> {
+    let options = PatternOptions {
+        limits,
+        ..Default::default()
+    };
+    Pattern::with_options(pattern, &options)
+}
+
+#[test]
+fn test_nest_limit() {
+    let limits = Limits {
+        nest_limit: 3,
+        ..Default::default()
+    };
+    assert!(with_limits("(((a)))", limits).is_ok());
+    let error = with_limits("((?:(?=(a))))", limits).unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::NestingTooDeep, 7..8)
+    );
+    // rejected before the parser recurses deep enough to overflow its stack
+    let deep = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+    let error = deep.parse::<Pattern>().unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::NestingTooDeep);
+}
+
+#[test]
+fn test_size_and_alternation_limits() {
+    let error = "(a{1000}){1000}".parse::<Pattern>().unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::PatternTooLarge);
+    let error = "((a{1000}){1000}){1000}".parse::<Pattern>().unwrap_err();
+    assert_eq!(
+        (error.kind, error.span),
+        (ParseErrorKind::PatternTooLarge, 0..23)
+    );
+    assert!("(a{1000}){10}".parse::<Pattern>().is_ok());
+
+    let limits = Limits {
+        alternation_limit: 3,
+        ..Default::default()
+    };
+    assert!(with_limits("a|(b|c)", limits).is_err());
+    assert!(with_limits("a|b|c", limits).is_ok());
+    let error = with_limits("a|b|c|d", limits).unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::TooManyAlternatives);
+}
+
+#[test]
+fn test_step_limit() {
+    let limits = Limits {
+        step_limit: Some(10_000),
+        ..Default::default()
+    };
+    // exponential for the backtracker, which the backreference needs
+    let pattern = with_limits(r"(x)?(a|aa)*[cd]\1", limits).unwrap();
+    let input = "a".repeat(40);
+    let exceeded = Err(MatchError::StepLimitExceeded { limit: 10_000 });
+    assert_eq!(pattern.try_matches_bytes(input.as_bytes()), exceeded);
+    assert!(!pattern.matches(&input));
+    assert_eq!(pattern.try_matches_bytes(b"xaac"), Ok(false));
+    assert_eq!(pattern.try_matches_bytes(b"xaacx"), Ok(true));
+
+    // the Pike VM counts its steps too
+    let pattern = with_limits(r"(a|b)*c", limits).unwrap();
+    let input = "ab".repeat(10_000) + "c";
+    let found = pattern.try_find_bytes_at(input.as_bytes(), 0);
+    assert_eq!(found.map(|found| found.is_some()), exceeded);
+}
+
+#[test]
+fn test_backtrack_limit() {
+    // exponential for the backtracker, stopped by the default limit instead of hanging
+    let pattern: Pattern = r"^((a|aa)+)\1*$".parse().unwrap();
+    let input = "a".repeat(40) + "!";
+    assert_eq!(
+        pattern.try_matches_bytes(input.as_bytes()),
+        Err(MatchError::BacktrackLimitExceeded { limit: 10_000_000 })
+    );
+    assert!(pattern.matches(&"a".repeat(40)));
+
+    let limits = Limits {
+        backtrack_limit: 100,
+        ..Default::default()
+    };
+    let pattern = with_limits(r"(a)\1b", limits).unwrap();
+    assert_eq!(pattern.try_matches_bytes(b"aab"), Ok(true));
+    let input = "a".repeat(100);
+    let exceeded = Err(MatchError::BacktrackLimitExceeded { limit: 100 });
+    assert_eq!(pattern.try_matches_bytes(input.as_bytes()), exceeded);
+    // the Pike VM doesn't count against it
+    let pattern = with_limits(r"(a|b)*c", limits).unwrap();
+    let input = "ab".repeat(10_000) + "c";
+    let found = pattern.try_find_bytes_at(input.as_bytes(), 0);
+    assert_eq!(found.map(|found| found.is_some()), Ok(true));
+}
+
+#[test]
+fn test_stack_limit() {
+    let pattern: Pattern = r"(a)a*\1b".parse().unwrap();
+    let input = "a".repeat(200_000);
+    let found = pattern.try_captures_bytes(input.as_bytes());
+    assert_eq!(
+        found.map(|found| found.is_some()),
+        Err(MatchError::StackLimitExceeded { limit: 1 << 20 })
+    );
+    assert!(pattern.matches("aaab"));
+}
@@ -1,91 +1,275 @@
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read, Write};
 use std::process;
+use std::thread;
 
-mod Pattern;
-mod pattern_matcher;
-
+#[allow(non_snake_case)]
 mod File;
 
-use anyhow::Error;
-use pattern_matcher::match_input;
+use rusty_grep::pattern_matcher::PatternMatcher;
+use rusty_grep::syntax::Syntax;
+use rusty_grep::Pattern::{MatchError, PatternOptions};
 
 use crate::File::_File;
 
-// Usage: echo <input_text> | your_program.sh -E <pattern>
+// Usage: echo <input_text> | your_program.sh [options] <patterns>
+//        your_program.sh [options] [-r] <patterns> <files or folder>
+// <patterns>: a pattern, -e <pattern> or -f <file with one pattern per line>
+// options: -G (basic syntax, the default) | -E (extended syntax) | -F (fixed strings),
+//          -i | --smart-case, -o (print only the matches),
+//          --leftmost-longest (POSIX match selection),
+//          --show-patterns (print which patterns matched each line, numbered from 1),
+//          -U (multiline: a match can span lines, ^ and $ match at every line),
+//          --step-limit <n>, --backtrack-limit <n>, --stack-limit <bytes> (see Limits)
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        println!("the input is not correct");
-        process::exit(1);
-    }
+    // like grep, a pattern is a basic regular expression unless -E is given
+    let mut options = PatternOptions {
+        syntax: Syntax::Basic,
+        ..Default::default()
+    };
+    let mut recursive = false;
+    let mut fixed_strings = false;
+    let mut only_matching = false;
+    let mut show_patterns = false;
+    let mut multiline = false;
+    let mut patterns = vec![];
+    let mut paths = vec![];
 
-    let pattern = args[2].clone();
-
-    if args.len() >= 4 {
-        if args[1] == "-r" {
-            // process folder (recursively search in that folder)
-            let folder_name = args[4].clone();
-            let pattern = args[3].clone();
-            let mut all_files = vec![];
-            match recursive_search(folder_name, &mut all_files) {
-                Ok(_) => process_files(all_files, pattern),
-                Err(e) => {
-                    eprintln!("Error reading folder: {}", e);
+    let mut args_iter = args.into_iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-e" => patterns.extend(args_iter.next()),
+            "-f" => {
+                let Some(patterns_file) = args_iter.next() else {
+                    println!("the input is not correct");
                     process::exit(1);
+                };
+                match read_patterns(&patterns_file) {
+                    Ok(read) => patterns.extend(read),
+                    Err(e) => {
+                        eprintln!("Error reading {}: {}", patterns_file, e);
+                        process::exit(1);
+                    }
                 }
             }
+            "-G" => (options.syntax, fixed_strings) = (Syntax::Basic, false),
+            "-E" => (options.syntax, fixed_strings) = (Syntax::Extended, false),
+            "-F" => fixed_strings = true,
+            "-o" => only_matching = true,
+            "--show-patterns" => show_patterns = true,
+            "-U" => {
+                // like ripgrep -U, the anchors still mean the start and end of a line
+                multiline = true;
+                options.multi_line = true;
+            }
+            "--leftmost-longest" => options.leftmost_longest = true,
+            "-r" => recursive = true,
+            "-i" => options.case_insensitive = true,
+            "--smart-case" => options.smart_case = true,
+            "--step-limit" => options.limits.step_limit = Some(limit_arg(args_iter.next())),
+            "--backtrack-limit" => options.limits.backtrack_limit = limit_arg(args_iter.next()),
+            "--stack-limit" => options.limits.stack_limit = limit_arg(args_iter.next()),
+            _ => paths.push(arg),
         }
-        let files = get_files_names_from_args(args);
-        process_files(files, pattern);
+    }
+    // without -e or -f the first argument that isn't an option is the pattern
+    if patterns.is_empty() && !paths.is_empty() {
+        patterns.push(paths.remove(0));
+    }
+    // compiled once, then shared by every file and thread of the search
+    let matcher = match patterns.as_slice() {
+        [] => {
+            println!("the input is not correct");
+            process::exit(1);
+        }
+        _ if fixed_strings => PatternMatcher::literals(&patterns, &options),
+        _ => PatternMatcher::patterns(&patterns, &options),
+    };
+    let matcher = match matcher {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            // like grep, a bad pattern is an error (2), not a pattern without matches (1)
+            eprintln!("invalid pattern: {}", e.message);
+            eprintln!("{}", e.diagnostic());
+            process::exit(2);
+        }
+    };
+
+    let output = Output {
+        only_matching,
+        show_patterns,
+        multiline,
+        // the backtracker may use stack_limit bytes, the rest is for what runs around it
+        stack_size: options.limits.stack_limit.saturating_add(1 << 20),
+    };
+    if recursive {
+        // process folder (recursively search in that folder)
+        let mut all_files = vec![];
+        for folder_name in paths {
+            if let Err(e) = recursive_search(folder_name, &mut all_files) {
+                eprintln!("Error reading folder: {}", e);
+                process::exit(1);
+            }
+        }
+        process_files(all_files, &matcher, &output);
+    } else if !paths.is_empty() {
+        process_files(paths, &matcher, &output);
     } else {
-        // process input from stdin
-        let mut input_line = String::new();
-        io::stdin().read_line(&mut input_line).unwrap();
-        process_input_from_stdin(input_line, pattern);
+        // process input from stdin, all of it in multiline mode
+        let mut input_line = vec![];
+        let mut stdin = io::stdin().lock();
+        if multiline {
+            stdin.read_to_end(&mut input_line).unwrap();
+        } else {
+            stdin.read_until(b'\n', &mut input_line).unwrap();
+        }
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(output.stack_size)
+                .spawn_scoped(scope, || process_input_from_stdin(input_line, &matcher))
+                .unwrap();
+        });
     }
 }
 
-fn process_files(files_names: Vec<String>, pattern: String) {
+// what is printed for a matched line
+struct Output {
+    only_matching: bool,
+    show_patterns: bool,
+    multiline: bool,
+    stack_size: usize, // of the threads that search
+}
+
+fn process_files(files_names: Vec<String>, matcher: &PatternMatcher, output: &Output) {
     let multiple_files = files_names.len() > 1;
     let mut any_match = false;
+    let mut any_error = false;
 
-    for file_name in files_names {
-        if let Ok(file) = _File::new(file_name.clone()) {
-            let lines_matched = file.match_file(pattern.as_str()); // Vec<&String>
-            if !lines_matched.is_empty() {
-                any_match = true;
-                for line in lines_matched {
-                    if multiple_files {
-                        println!("{file_name}:{line}");
-                    } else {
-                        println!("{line}");
-                    }
+    // the files are split between the threads, and printed in their order once all are searched
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = files_names.len().div_ceil(threads).max(1);
+    let results: Vec<Result<Vec<Vec<u8>>, MatchError>> = thread::scope(|scope| {
+        let workers: Vec<_> = files_names
+            .chunks(chunk_size)
+            .map(|chunk| {
+                thread::Builder::new()
+                    .stack_size(output.stack_size)
+                    .spawn_scoped(scope, move || {
+                        chunk
+                            .iter()
+                            .map(|file_name| match_file(file_name, matcher, output))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap()
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    // the lines are written byte for byte, as they are in the file
+    let mut out = io::stdout().lock();
+    for (file_name, result) in files_names.iter().zip(results) {
+        // the search went past one of the limits, what it found so far isn't printed
+        let lines_matched = match result {
+            Ok(lines_matched) => lines_matched,
+            Err(e) => {
+                eprintln!("{file_name}: {e}");
+                any_error = true;
+                continue;
+            }
+        };
+        if !lines_matched.is_empty() {
+            any_match = true;
+            for line in lines_matched {
+                let mut prefix = String::new();
+                if multiple_files {
+                    prefix = format!("{file_name}:");
+                }
+                if output.show_patterns {
+                    let numbers: Vec<String> = matcher
+                        .matching_patterns(&line)
+                        .iter()
+                        .map(|index| (index + 1).to_string())
+                        .collect();
+                    prefix = format!("{prefix}{}:", numbers.join(","));
+                }
+                // in multiline mode the matches were cut out when the file was searched
+                let written = if output.only_matching && !output.multiline {
+                    // like grep -o, each match on its own line, the empty ones are skipped
+                    let spans = matcher.try_find_spans(&line).unwrap_or_else(|e| {
+                        eprintln!("{file_name}: {e}");
+                        any_error = true;
+                        vec![]
+                    });
+                    spans
+                        .into_iter()
+                        .filter(|span| !span.is_empty())
+                        .try_for_each(|span| print_line(&mut out, &prefix, &line[span]))
+                } else {
+                    print_line(&mut out, &prefix, &line)
+                };
+                // the reader went away (| head), nothing more to print
+                if written.is_err() {
+                    break;
                 }
             }
         }
     }
+    let _ = out.flush();
 
-    std::process::exit(if any_match { 0 } else { 1 });
+    // like grep, an error wins over the matches
+    std::process::exit(match (any_error, any_match) {
+        (true, _) => 2,
+        (false, true) => 0,
+        (false, false) => 1,
+    });
 }
 
-fn print_lines(lines_content: Vec<&String>, file_name: String) {
-    for line_content in lines_content {
-        if file_name.is_empty() {
-            println!("{line_content}");
-        } else {
-            println!("{file_name}:{line_content}");
-        }
+fn print_line(out: &mut impl Write, prefix: &str, text: &[u8]) -> io::Result<()> {
+    out.write_all(prefix.as_bytes())?;
+    out.write_all(text)?;
+    out.write_all(b"\n")
+}
+
+// the matching lines of one file, none when it can't be read
+fn match_file(
+    file_name: &str,
+    matcher: &PatternMatcher,
+    output: &Output,
+) -> Result<Vec<Vec<u8>>, MatchError> {
+    match _File::new(file_name.to_string()) {
+        Ok(file) if output.multiline => file.match_file_multiline(matcher, output.only_matching),
+        Ok(file) => Ok(file
+            .match_file(matcher)?
+            .into_iter()
+            .map(|line| line.to_vec())
+            .collect()),
+        Err(_) => Ok(vec![]),
     }
 }
-fn get_files_names_from_args(args: Vec<String>) -> Vec<String> {
-    let mut files_names = vec![];
-    for file_name in &args[3..] {
-        files_names.push(file_name.clone());
+
+// the number after --step-limit, --backtrack-limit or --stack-limit
+fn limit_arg(arg: Option<String>) -> usize {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(limit) => limit,
+        None => {
+            println!("the input is not correct");
+            process::exit(1);
+        }
     }
-    files_names
 }
+
+// one pattern per line, like grep -f
+fn read_patterns(file_name: &str) -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(file_name)?;
+    Ok(content.lines().map(|line| line.to_string()).collect())
+}
+
 fn recursive_search(
     folder_path: String,
     founded_files: &mut Vec<String>,
@@ -95,20 +279,30 @@ fn recursive_search(
         let path = entry.path();
 
         if path.is_dir() {
-            recursive_search(path.to_string_lossy().to_string(), founded_files);
+            // a folder that can't be read is skipped, its files are not searched
+            let _ = recursive_search(path.to_string_lossy().to_string(), founded_files);
         } else {
             founded_files.push(path.to_string_lossy().to_string());
         }
     }
-    return Ok(founded_files);
+    Ok(founded_files)
 }
 
-fn process_input_from_stdin(input_line: String, pattern: String) {
-    if match_input(&input_line, &pattern) {
-        println!("input mathc the pattern");
-        process::exit(0)
-    } else {
-        println!("input didn't match the pattern");
-        process::exit(1)
+fn process_input_from_stdin(input_line: Vec<u8>, matcher: &PatternMatcher) {
+    // like in files, the newline ends the line but isn't part of it (so $ can match before it)
+    let input_line = input_line.strip_suffix(b"\n").unwrap_or(&input_line);
+    match matcher.try_is_match_bytes(input_line) {
+        Ok(true) => {
+            println!("input mathc the pattern");
+            process::exit(0)
+        }
+        Ok(false) => {
+            println!("input didn't match the pattern");
+            process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2)
+        }
     }
 }
@@ -0,0 +1,792 @@
+use crate::utf8;
+use crate::Pattern::{Assertion, Group, MatchState, SubPattern, Token};
+
+// ------------------------------------------------------------------------------//
+//                              Thompson NFA program                             //
+// ------------------------------------------------------------------------------//
+
+// one instruction of the program, every thread of the Pike VM sits on one of them
+#[derive(Debug, PartialEq)]
+pub enum Inst {
+    Char(Token),         // consume one char the token accepts (a literal, a class, .)
+    Split(usize, usize), // go on at both, the first one has the higher priority
+    Jump(usize),
+    Save(usize), // store the position: slot 2 * group is its start, 2 * group + 1 its end
+    Assert(Assertion), // ^ $ \b ... checked without consuming anything
+    Match(usize), // the index of the pattern that matched, in a PatternSet
+    // go on at the target when the position is the one saved in the slot: the iteration of
+    // a repeat that started there matched nothing and leaves the repeat
+    ExitIfEmpty(usize, usize),
+}
+
+// the pattern compiled to a list of instructions, run by the Pike VM in Program::search
+// every thread moves one char at a time, so the time is linear in the length of the input
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    pub(crate) insts: Vec<Inst>,
+    slot_count: usize,
+    // the iterations with an ExitIfEmpty: the slot of their position and the one around them
+    loops: Vec<(usize, Option<usize>)>,
+    // the innermost of those iterations each instruction is in
+    in_loop: Vec<Option<usize>>,
+    // threads on one instruction differ by how many iterations around it are still empty
+    // (an ExitIfEmpty sends them different ways): 0 to the deepest nesting of those iterations
+    levels: usize,
+}
+
+impl Program {
+    // None when the pattern needs the backtracker:
+    // backreferences, lookarounds and atomic groups can't be run one char at a time
+    pub fn compile(sub_patterns: &[SubPattern], group_count: usize) -> Option<Program> {
+        let mut compiler = Compiler::new(group_count);
+        compiler.emit(Inst::Save(0));
+        compiler.compile_alternatives(sub_patterns)?;
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match(0));
+        Some(compiler.finish())
+    }
+
+    // the patterns of a PatternSet in one program: a Split to each of them, and each one
+    // ends on a Match with its index (and saves the span of its match like a single pattern)
+    // None when one of them needs the backtracker
+    // every pattern numbers its groups from 1, they share the slots of the most groups
+    pub fn compile_set(patterns: &[&[SubPattern]], group_count: usize) -> Option<Program> {
+        let mut compiler = Compiler::new(group_count);
+        for (index, sub_patterns) in patterns.iter().enumerate() {
+            let is_last = index + 1 == patterns.len();
+            let split = (!is_last).then(|| compiler.emit_split());
+            compiler.emit(Inst::Save(0));
+            compiler.compile_alternatives(sub_patterns)?;
+            compiler.emit(Inst::Save(1));
+            compiler.emit(Inst::Match(index));
+            if let Some(split) = split {
+                let next_pattern = compiler.insts.len();
+                compiler.patch_split(split, split + 1, next_pattern, true);
+            }
+        }
+        Some(compiler.finish())
+    }
+
+    // mark every pattern of a set program that matches somewhere in the input, in one pass
+    // there is no first match to stop at: the threads of every pattern run to the end,
+    // unless all the patterns matched already
+    pub fn search_set(&self, state: &mut MatchState, matched: &mut [bool]) {
+        let input = state.input;
+        let mut current = Threads::new(self);
+        let mut next = Threads::new(self);
+        let mut stack = vec![];
+        let mut left = self
+            .insts
+            .iter()
+            .filter(|inst| matches!(inst, Inst::Match(pattern) if !matched[*pattern]))
+            .count();
+        let mut pos = 0;
+        while left > 0 {
+            let start_slots = vec![None; self.slot_count];
+            self.add_thread(&mut current, &mut stack, state, 0, pos, start_slots);
+            if !state.step(current.len()) {
+                return;
+            }
+            let c = utf8::decode(&input[pos..]);
+            for index in 0..current.len() {
+                let pc = current.pc(index);
+                match &self.insts[pc] {
+                    Inst::Char(token) => {
+                        if let Some((_, len)) = c.filter(|(c, _)| token.match_char(*c, false)) {
+                            let slots = current.slots(index).to_vec();
+                            self.add_thread(&mut next, &mut stack, state, pc + 1, pos + len, slots);
+                        }
+                    }
+                    Inst::Match(pattern) => {
+                        if !std::mem::replace(&mut matched[*pattern], true) {
+                            left -= 1;
+                        }
+                    }
+                    _ => unreachable!("only Char and Match threads are kept"),
+                }
+            }
+            match c {
+                Some((_, len)) => pos += len,
+                None => break,
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+    }
+
+    // the leftmost match, with the same priorities as the backtracker:
+    // earlier alternatives first, greedy quantifiers take as much as they can, lazy as little
+    // on success the span of every group is written to the slots of the state
+    // `start` is the first offset a match may start at
+    // with `leftmost_longest` the longest of the matches starting the most on the left wins
+    pub fn search(&self, state: &mut MatchState, start: usize, leftmost_longest: bool) -> bool {
+        self.search_leftmost(state, start, leftmost_longest)
+            .is_some()
+    }
+
+    // the same, and the index of the pattern whose Match the match ended on
+    // in a set program the patterns are alternatives, on a tie the first one wins
+    pub fn search_leftmost(
+        &self,
+        state: &mut MatchState,
+        start: usize,
+        leftmost_longest: bool,
+    ) -> Option<usize> {
+        let input = state.input;
+        let mut current = Threads::new(self);
+        let mut next = Threads::new(self);
+        let mut stack = vec![];
+        let mut matched: Option<Vec<Option<usize>>> = None;
+        let mut matched_pattern = 0;
+        let mut pos = start;
+        loop {
+            // a thread starting here has a lower priority than the ones that started before
+            if matched.is_none() {
+                let start_slots = vec![None; self.slot_count];
+                self.add_thread(&mut current, &mut stack, state, 0, pos, start_slots);
+            }
+            // nothing left that could beat the match we have
+            if current.is_empty() && matched.is_some() {
+                break;
+            }
+            if !state.step(current.len()) {
+                return None;
+            }
+            let c = utf8::decode(&input[pos..]);
+            for index in 0..current.len() {
+                let pc = current.pc(index);
+                match &self.insts[pc] {
+                    Inst::Char(_)
+                        if leftmost_longest && starts_after(current.slots(index), &matched) =>
+                    {
+                        // can't beat a match that starts before it
+                    }
+                    Inst::Char(token) => {
+                        if let Some((_, len)) = c.filter(|(c, _)| token.match_char(*c, false)) {
+                            let slots = current.slots(index).to_vec();
+                            let after = pos + len;
+                            self.add_thread(&mut next, &mut stack, state, pc + 1, after, slots);
+                        }
+                    }
+                    Inst::Match(pattern) if leftmost_longest => {
+                        // the threads after this one may still match longer from the same start
+                        let slots = current.slots(index);
+                        if is_leftmost_longer(slots, &matched) {
+                            matched = Some(slots.to_vec());
+                            matched_pattern = *pattern;
+                        }
+                    }
+                    Inst::Match(pattern) => {
+                        // the threads after this one have a lower priority, drop them
+                        matched = Some(current.slots(index).to_vec());
+                        matched_pattern = *pattern;
+                        break;
+                    }
+                    _ => unreachable!("only Char and Match threads are kept"),
+                }
+            }
+            match c {
+                Some((_, len)) => pos += len,
+                None => break,
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        let slots = matched?;
+        for (group, span) in state.slots.iter_mut().enumerate() {
+            *span = slots[2 * group].zip(slots[2 * group + 1]);
+        }
+        Some(matched_pattern)
+    }
+
+    // follow the Jump, Split, Save and Assert instructions from `pc` and add a thread
+    // for every Char or Match reached, in priority order
+    // a pc already in the list is skipped: an earlier thread got there with a higher priority
+    // (unless one of them is in an empty iteration of a repeat around the pc and the other isn't)
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        stack: &mut Vec<Frame>,
+        state: &MatchState,
+        pc: usize,
+        pos: usize,
+        mut slots: Vec<Option<usize>>,
+    ) {
+        stack.push(Frame::Explore(pc));
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::RestoreSlot(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            let id = pc * self.levels + self.empty_loops(pc, &slots, pos);
+            if !threads.insert(id) {
+                continue;
+            }
+            match &self.insts[pc] {
+                Inst::Jump(target) => stack.push(Frame::Explore(*target)),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(*second));
+                    stack.push(Frame::Explore(*first));
+                }
+                Inst::Save(slot) => {
+                    // the other paths from the Split above don't see this position
+                    stack.push(Frame::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Assert(assertion) => {
+                    if Token::match_assertion(assertion, state, pos) {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::ExitIfEmpty(slot, exit) => match slots[*slot] == Some(pos) {
+                    true => stack.push(Frame::Explore(*exit)),
+                    false => stack.push(Frame::Explore(pc + 1)),
+                },
+                Inst::Char(_) | Inst::Match(_) => threads.set_slots(id, &slots),
+            }
+        }
+    }
+
+    // how many of the iterations around `pc` started at `pos`
+    // an outer one started before or with the inner one, they are the innermost
+    fn empty_loops(&self, pc: usize, slots: &[Option<usize>], pos: usize) -> usize {
+        let mut count = 0;
+        let mut current = self.in_loop[pc];
+        while let Some((slot, outer)) = current.map(|index| self.loops[index]) {
+            if slots[slot] != Some(pos) {
+                break;
+            }
+            count += 1;
+            current = outer;
+        }
+        count
+    }
+}
+
+// slots 0 and 1 of a thread are where its match starts and ends
+fn starts_after(slots: &[Option<usize>], matched: &Option<Vec<Option<usize>>>) -> bool {
+    matched
+        .as_ref()
+        .is_some_and(|matched| slots[0] > matched[0])
+}
+
+// starts before the match we have, or at the same place and ends after it
+fn is_leftmost_longer(slots: &[Option<usize>], matched: &Option<Vec<Option<usize>>>) -> bool {
+    match matched {
+        Some(matched) => {
+            (slots[0], std::cmp::Reverse(slots[1])) < (matched[0], std::cmp::Reverse(matched[1]))
+        }
+        None => true,
+    }
+}
+
+enum Frame {
+    Explore(usize),
+    RestoreSlot(usize, Option<usize>),
+}
+
+// the threads of one step in priority order, at most one per instruction and level
+// a thread's id is pc * levels + level, see Program::levels
+struct Threads {
+    ids: Vec<usize>,
+    seen: Vec<bool>,
+    // the slots of the thread with an id are at id * slot_count
+    slots: Vec<Option<usize>>,
+    slot_count: usize,
+    levels: usize,
+}
+
+impl Threads {
+    fn new(program: &Program) -> Self {
+        let ids = program.insts.len() * program.levels;
+        Threads {
+            ids: vec![],
+            seen: vec![false; ids],
+            slots: vec![None; ids * program.slot_count],
+            slot_count: program.slot_count,
+            levels: program.levels,
+        }
+    }
+
+    // false when the instruction was already visited at this level in this step
+    fn insert(&mut self, id: usize) -> bool {
+        if self.seen[id] {
+            return false;
+        }
+        self.seen[id] = true;
+        true
+    }
+
+    fn set_slots(&mut self, id: usize, slots: &[Option<usize>]) {
+        self.ids.push(id);
+        self.slots[id * self.slot_count..(id + 1) * self.slot_count].copy_from_slice(slots);
+    }
+
+    fn pc(&self, index: usize) -> usize {
+        self.ids[index] / self.levels
+    }
+
+    fn slots(&self, index: usize) -> &[Option<usize>] {
+        let id = self.ids[index];
+        &self.slots[id * self.slot_count..(id + 1) * self.slot_count]
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.ids.clear();
+        self.seen.fill(false);
+    }
+}
+
+// ------------------------------------------------------------------------------//
+//                                    Compiler                                   //
+// ------------------------------------------------------------------------------//
+
+// the number of instructions Program::compile emits for the pattern, without emitting them
+// the tokens only the backtracker runs count as if they were compiled too
+// saturates instead of overflowing on patterns like ((a{1000}){1000}){1000}
+pub fn compiled_size(sub_patterns: &[SubPattern]) -> usize {
+    // Save(0) Save(1) Match
+    alternatives_size(sub_patterns).saturating_add(3)
+}
+
+// a Split and a Jump for every alternative but the last
+fn alternatives_size(alternatives: &[SubPattern]) -> usize {
+    let splits = 2 * alternatives.len().saturating_sub(1);
+    alternatives
+        .iter()
+        .flat_map(|alternative| &alternative.tokens)
+        .fold(splits, |size, token| size.saturating_add(token_size(token)))
+}
+
+fn token_size(token: &Token) -> usize {
+    match token {
+        Token::Literal(_)
+        | Token::WildCard
+        | Token::AnyChar
+        | Token::CharClass(_)
+        | Token::GroupClass(_)
+        | Token::Range(..)
+        | Token::BackReference(_)
+        | Token::NamedBackReference(_)
+        | Token::Assertion(_) => 1,
+        Token::Text(text) => text.chars().count(),
+        Token::CaseInsensitive(token) => token_size(token),
+        Token::SetFlags(_) => 0,
+        Token::Group(Group {
+            alternatives,
+            capture,
+        }) => match capture {
+            Some(_) => alternatives_size(alternatives).saturating_add(2),
+            None => alternatives_size(alternatives),
+        },
+        Token::LookAround(look_around) => alternatives_size(&look_around.alternatives) + 1,
+        Token::Atomic(token) => token_size(token).saturating_add(1),
+        Token::Lazy(quantifier) => repeat_size(quantifier, false),
+        Token::OneORMore(_) | Token::OneOrNone(_) | Token::ZeroOrMore(_) | Token::Repeat(..) => {
+            repeat_size(token, true)
+        }
+    }
+}
+
+// what compile_repeat emits
+fn repeat_size(quantifier: &Token, greedy: bool) -> usize {
+    let Some((token, min, max)) = quantifier.repeat_range() else {
+        return 1;
+    };
+    let size = token_size(token);
+    // and a Save and an ExitIfEmpty around an iteration that can be empty
+    let empty = token.can_match_empty();
+    let iteration = match empty {
+        true => size.saturating_add(2),
+        false => size,
+    };
+    match max {
+        // then x+ is only the loop, without a copy of x before it
+        None if empty && greedy && matches!(quantifier, Token::OneORMore(_)) => iteration + 1,
+        None => min
+            .saturating_mul(size)
+            .saturating_add(iteration)
+            .saturating_add(2),
+        Some(max) => min
+            .saturating_mul(size)
+            .saturating_add((max - min).saturating_mul(iteration.saturating_add(1))),
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    // the slots of the groups, then one for each repeat that needs an ExitIfEmpty
+    slot_count: usize,
+    // see Program, with the iteration the next instruction is in
+    loops: Vec<(usize, Option<usize>)>,
+    in_loop: Vec<Option<usize>>,
+    current_loop: Option<usize>,
+    levels: usize,
+}
+
+impl Compiler {
+    fn new(group_count: usize) -> Self {
+        Compiler {
+            insts: vec![],
+            slot_count: 2 * (group_count + 1),
+            loops: vec![],
+            in_loop: vec![],
+            current_loop: None,
+            levels: 1,
+        }
+    }
+
+    fn finish(self) -> Program {
+        Program {
+            insts: self.insts,
+            slot_count: self.slot_count,
+            loops: self.loops,
+            in_loop: self.in_loop,
+            levels: self.levels,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.in_loop.push(self.current_loop);
+        self.insts.len() - 1
+    }
+
+    // a Split whose targets are filled in by patch_split once they are known
+    fn emit_split(&mut self) -> usize {
+        self.emit(Inst::Split(0, 0))
+    }
+
+    // `greedy` puts the `body` target first, so the VM prefers it over `skip`
+    fn patch_split(&mut self, split: usize, body: usize, skip: usize, greedy: bool) {
+        self.insts[split] = if greedy {
+            Inst::Split(body, skip)
+        } else {
+            Inst::Split(skip, body)
+        };
+    }
+
+    // a|b|c  →  Split(a, Split(b, c)), each alternative jumps to the end
+    fn compile_alternatives(&mut self, alternatives: &[SubPattern]) -> Option<()> {
+        let mut jumps = vec![];
+        for (index, alternative) in alternatives.iter().enumerate() {
+            let is_last = index + 1 == alternatives.len();
+            let split = (!is_last).then(|| self.emit_split());
+            for token in &alternative.tokens {
+                self.compile_token(token)?;
+            }
+            if let Some(split) = split {
+                jumps.push(self.emit(Inst::Jump(0)));
+                let next_alternative = self.insts.len();
+                self.patch_split(split, split + 1, next_alternative, true);
+            }
+        }
+        let end = self.insts.len();
+        for jump in jumps {
+            self.insts[jump] = Inst::Jump(end);
+        }
+        Some(())
+    }
+
+    fn compile_token(&mut self, token: &Token) -> Option<()> {
+        match token {
+            Token::Literal(_)
+            | Token::WildCard
+            | Token::AnyChar
+            | Token::CharClass(_)
+            | Token::GroupClass(_) => {
+                self.emit(Inst::Char(token.clone()));
+            }
+            // one char at a time
+            Token::Text(text) => {
+                for c in text.chars() {
+                    self.emit(Inst::Char(Token::Literal(c)));
+                }
+            }
+            Token::CaseInsensitive(inner) => match inner.as_ref() {
+                Token::BackReference(_) => return None,
+                Token::Text(text) => {
+                    for c in text.chars() {
+                        let literal = Box::new(Token::Literal(c));
+                        self.emit(Inst::Char(Token::CaseInsensitive(literal)));
+                    }
+                }
+                _ => {
+                    self.emit(Inst::Char(token.clone()));
+                }
+            },
+            Token::Group(Group {
+                alternatives,
+                capture,
+            }) => match capture {
+                Some(capture) => {
+                    self.emit(Inst::Save(2 * capture.index));
+                    self.compile_alternatives(alternatives)?;
+                    self.emit(Inst::Save(2 * capture.index + 1));
+                }
+                None => self.compile_alternatives(alternatives)?,
+            },
+            Token::Assertion(assertion) => {
+                self.emit(Inst::Assert(assertion.clone()));
+            }
+            Token::SetFlags(_) => {} // already applied by the parser
+            Token::OneORMore(_)
+            | Token::OneOrNone(_)
+            | Token::ZeroOrMore(_)
+            | Token::Repeat(..) => self.compile_repeat(token, true)?,
+            Token::Lazy(quantifier) => self.compile_repeat(quantifier, false)?,
+            Token::Range(..)
+            | Token::BackReference(_)
+            | Token::NamedBackReference(_)
+            | Token::LookAround(_)
+            | Token::Atomic(_) => return None,
+        }
+        Some(())
+    }
+
+    // {min,max}: the token min times, then max - min optional copies (or a loop when no max)
+    // like in the backtracker, an iteration past the min that matches nothing leaves the
+    // repeat with its priority (another one from the same place couldn't lead anywhere new),
+    // and so does the first one of a greedy x+
+    fn compile_repeat(&mut self, quantifier: &Token, greedy: bool) -> Option<()> {
+        let (token, min, max) = quantifier.repeat_range()?;
+        let slot = token.can_match_empty().then(|| self.new_slot());
+        let plus = slot.is_some() && greedy && matches!(quantifier, Token::OneORMore(_));
+        for _ in 0..min - plus as usize {
+            self.compile_token(token)?;
+        }
+        match max {
+            // x* is compiled as (x+)?, so a first empty iteration still records its groups
+            None => {
+                let skip = (!plus).then(|| self.emit_split());
+                let body = self.insts.len();
+                let check = self.compile_iteration(token, slot)?;
+                let again = self.emit_split();
+                let end = self.insts.len();
+                self.patch_checks(check, end);
+                self.patch_split(again, body, end, greedy);
+                if let Some(skip) = skip {
+                    self.patch_split(skip, body, end, greedy);
+                }
+            }
+            // x{2,4} is xx(x(x)?)?
+            Some(max) => {
+                let mut splits = vec![];
+                let mut checks = vec![];
+                for _ in min..max {
+                    splits.push(self.emit_split());
+                    checks.extend(self.compile_iteration(token, slot)?);
+                }
+                let end = self.insts.len();
+                for split in splits {
+                    self.patch_split(split, split + 1, end, greedy);
+                }
+                self.patch_checks(checks, end);
+            }
+        }
+        Some(())
+    }
+
+    // one iteration of a repeat, when it can be empty it saves the position it starts at and
+    // ends with an ExitIfEmpty, whose target is filled in by patch_checks
+    fn compile_iteration(&mut self, token: &Token, slot: Option<usize>) -> Option<Option<usize>> {
+        let Some(slot) = slot else {
+            self.compile_token(token)?;
+            return Some(None);
+        };
+        self.emit(Inst::Save(slot));
+        self.loops.push((slot, self.current_loop));
+        self.current_loop = Some(self.loops.len() - 1);
+        let mut depth = 0;
+        let mut current = self.current_loop;
+        while let Some(index) = current {
+            depth += 1;
+            current = self.loops[index].1;
+        }
+        self.levels = self.levels.max(depth + 1);
+        self.compile_token(token)?;
+        let check = self.emit(Inst::ExitIfEmpty(slot, 0));
+        self.current_loop = self.current_loop.and_then(|index| self.loops[index].1);
+        Some(Some(check))
+    }
+
+    fn patch_checks(&mut self, checks: impl IntoIterator<Item = usize>, end: usize) {
+        for check in checks {
+            if let Inst::ExitIfEmpty(_, exit) = &mut self.insts[check] {
+                *exit = end;
+            }
+        }
+    }
+
+    // a slot for the position an iteration of a repeat starts at, after those of the groups
+    fn new_slot(&mut self) -> usize {
+        self.slot_count += 1;
+        self.slot_count - 1
+    }
+}
+
+#[cfg(test)]
+use crate::Pattern::Pattern;
+
+#[test]
+fn test_compile_alternation_and_star() {
+    let pattern: Pattern = "a|b*".parse().unwrap();
+    let program = Program::compile(&pattern.sub_patterns, 0).unwrap();
+    assert_eq!(
+        program.insts,
+        vec![
+            Inst::Save(0),
+            Inst::Split(2, 4),
+            Inst::Char(Token::Literal('a')),
+            Inst::Jump(7),
+            Inst::Split(5, 7),
+            Inst::Char(Token::Literal('b')),
+            Inst::Split(5, 7),
+            Inst::Save(1),
+            Inst::Match(0),
+        ]
+    );
+}
+
+#[test]
+fn test_compiled_size() {
+    for pattern in [
+        "a|b*",
+        r"(a|bc)+?\d{2,4}x*",
+        "(?:ab){3}|(c)?",
+        "^(a*){2,}$",
+        "(a?)+(b|){1,3}?(?:c*)+?",
+    ] {
+        let pattern: Pattern = pattern.parse().unwrap();
+        let program = Program::compile(&pattern.sub_patterns, pattern.group_count()).unwrap();
+        assert_eq!(compiled_size(&pattern.sub_patterns), program.insts.len());
+    }
+}
+
+#[test]
+fn test_compile_needs_backtracking() {
+    for pattern in [r"(a)\1", "a(?=b)", "(?>a+)b", "a++"] {
+        let pattern: Pattern = pattern.parse().unwrap();
+        let program = Program::compile(&pattern.sub_patterns, pattern.group_count());
+        assert!(program.is_none());
+    }
+}
+
+#[test]
+fn test_pike_vm_is_linear_on_pathological_patterns() {
+    // each of these takes exponential time with backtracking
+    let pattern: Pattern = "^(a?){25}a{25}$".parse().unwrap();
+    assert!(pattern.matches(&"a".repeat(25)));
+
+    let pattern: Pattern = r"\w+\w+\w+x".parse().unwrap();
+    assert!(!pattern.matches(&"word".repeat(2000)));
+
+    let pattern: Pattern = "(x+x+)+y".parse().unwrap();
+    assert!(!pattern.matches(&"x".repeat(5000)));
+}
+
+#[test]
+fn test_pike_vm_agrees_with_the_backtracker() {
+    let cases = [
+        ("(a|ab)(c|bcd)(d*)", "abcd"),
+        ("(a*)+$", "b"),
+        ("(?:(a)|(b))+c", "abac"),
+        ("(a+?)(a*)", "aaa"),
+        ("x{2,4}?y", "xxxxy"),
+        (r"\b(\w+)\s(\w+)\b", "  hello   world "),
+        ("(?i)(HELLO) (w.rld)", "say Hello World"),
+        ("^$", ""),
+        ("(?s)a(.*)b", "a\nxb\nb"),
+        // an empty repetition of a bounded repeat is taken once, then the loop ends
+        (r"([ab]??){1,2}\B.", "AbA"),
+        ("(|.){1,2}", "ab"),
+        ("(|.){1,2}?x", "abx"),
+        ("(a??){2,3}(b)", "ab"),
+        // and so is one of a * or + loop
+        ("(?:b?|.+)*", "bcbbc"),
+        ("(?:.??)+a", "cabaa"),
+        ("(.??|[ab]ab?)+[ab]", "cabaa"),
+        ("(a|b?)*?c", "abc"),
+        // the first iteration of x+ too, and the optional ones of x{min,max}
+        ("(?:([ab]*|.??.?b{0,2}?)^|.+)+b", "cbaa"),
+        (r"(?:(\B[ab]+$|[ab]??.*?)?|\b$\b){0,2}?[ab]{1,2}", "cccbca"),
+    ];
+    for (pattern, input) in cases {
+        assert_engines_agree(pattern, input);
+    }
+}
+
+#[cfg(test)]
+fn assert_engines_agree(pattern: &str, input: &str) {
+    let pattern: Pattern = pattern.parse().unwrap();
+    let group_count = pattern.group_count();
+    let program = Program::compile(&pattern.sub_patterns, group_count).unwrap();
+    let mut vm = MatchState::new(input.as_bytes(), group_count);
+    let mut backtracker = MatchState::new(input.as_bytes(), group_count);
+    assert_eq!(
+        program.search(&mut vm, 0, false),
+        pattern.backtrack(&mut backtracker, 0),
+        "{:?} on {:?}",
+        pattern,
+        input
+    );
+    assert_eq!(vm.slots, backtracker.slots, "{:?} on {:?}", pattern, input);
+}
+
+#[test]
+fn test_pike_vm_agrees_with_the_backtracker_on_random_patterns() {
+    // xorshift, the same patterns on every run
+    let mut seed = 0x9e37_79b9_u32;
+    let mut random = |n: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as usize % n
+    };
+    for _ in 0..3000 {
+        let pattern = random_alternative(&mut random, 2);
+        let input: String = (0..random(6)).map(|_| ['a', 'b', 'c'][random(3)]).collect();
+        assert_engines_agree(&pattern, &input);
+    }
+}
+
+// a few chars, assertions and groups, the ones that can be repeated with a quantifier
+#[cfg(test)]
+fn random_alternative(random: &mut impl FnMut(usize) -> usize, depth: usize) -> String {
+    const REPEATED: [&str; 5] = ["a", "b", ".", "[ab]", ""];
+    const ZERO_WIDTH: [&str; 4] = [r"\b", r"\B", "^", "$"];
+    const QUANTIFIERS: [&str; 10] = ["", "", "*", "+", "?", "*?", "+?", "??", "{1,2}", "{0,2}?"];
+    let mut text = String::new();
+    for _ in 0..1 + random(3) {
+        let repeated = match random(4) {
+            0 => {
+                text.push_str(ZERO_WIDTH[random(ZERO_WIDTH.len())]);
+                continue;
+            }
+            1 if depth > 0 => format!(
+                "{}{}|{})",
+                ["(", "(?:"][random(2)],
+                random_alternative(random, depth - 1),
+                random_alternative(random, depth - 1)
+            ),
+            _ => REPEATED[random(REPEATED.len())].to_string(),
+        };
+        text.push_str(&repeated);
+        if !repeated.is_empty() {
+            text.push_str(QUANTIFIERS[random(QUANTIFIERS.len())]);
+        }
+    }
+    text
+}
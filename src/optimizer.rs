@@ -0,0 +1,503 @@
+use crate::Pattern::{Group, GroupClass, LookAround, SubPattern, Token};
+
+// ------------------------------------------------------------------------------//
+//                                   Optimizer                                   //
+// ------------------------------------------------------------------------------//
+
+// rewrite the parsed tree into a smaller one the engines run instead: it matches the same
+// text, captures the same spans and tries the alternatives in the same order
+//   abc              →  Text("abc")          one token instead of three
+//   foo|foobar|fob   →  fo(?:o(?:|bar)|b)    a prefix the alternatives share is matched once
+//   abc|xbc          →  (?:a|x)bc
+//   (?:a+)+  →  a+     a{0,1}  →  a?     a{3}  →  aaa     [a]  →  a     (?:ab)c  →  abc
+// (?i) and the other flags are dropped, the parser already applied them to the tokens
+pub fn optimize(sub_patterns: &[SubPattern]) -> Vec<SubPattern> {
+    let optimizer = Optimizer {
+        has_back_references: sub_patterns
+            .iter()
+            .any(|sub_pattern| has_back_references(&sub_pattern.tokens)),
+    };
+    optimizer.alternatives(sub_patterns)
+}
+
+// how many groups factoring may nest into each other, a|ab|abc|abcd... would get one more
+// level for every alternative
+const MAX_FACTOR_DEPTH: usize = 16;
+
+struct Optimizer {
+    // a backreference can tell (a+)+ from (a+) by what the group captured on the way
+    has_back_references: bool,
+}
+
+impl Optimizer {
+    fn alternatives(&self, alternatives: &[SubPattern]) -> Vec<SubPattern> {
+        let alternatives = alternatives
+            .iter()
+            .map(|alternative| self.tokens(&alternative.tokens))
+            .collect();
+        factor_suffix(factor_prefixes(alternatives, 0))
+            .into_iter()
+            .map(|tokens| SubPattern { tokens })
+            .collect()
+    }
+
+    // one alternative: the groups that don't capture and have a single alternative are
+    // spliced into it, then the literals next to each other merged
+    fn tokens(&self, tokens: &[Token]) -> Vec<Token> {
+        let mut optimized = vec![];
+        for token in tokens {
+            match self.token(token) {
+                Token::SetFlags(_) => {}
+                Token::Group(Group {
+                    mut alternatives,
+                    capture: None,
+                }) if alternatives.len() == 1 => optimized.append(&mut alternatives[0].tokens),
+                token => optimized.push(token),
+            }
+        }
+        merge_literals(optimized)
+    }
+
+    fn token(&self, token: &Token) -> Token {
+        match token {
+            Token::Group(Group {
+                alternatives,
+                capture,
+            }) => Token::Group(Group {
+                alternatives: self.alternatives(alternatives),
+                capture: capture.clone(),
+            }),
+            Token::LookAround(LookAround {
+                alternatives,
+                direction,
+                negated,
+            }) => Token::LookAround(LookAround {
+                alternatives: self.alternatives(alternatives),
+                direction: direction.clone(),
+                negated: *negated,
+            }),
+            // a class of one char is that char  [a]  [\d]
+            Token::GroupClass(GroupClass::MatchOne(items)) => match items.as_slice() {
+                [Token::Range(start, end)] if start == end => Token::Literal(*start),
+                [item @ (Token::Literal(_) | Token::CharClass(_))] => item.clone(),
+                _ => token.clone(),
+            },
+            Token::CaseInsensitive(inner) => Token::CaseInsensitive(Box::new(self.token(inner))),
+            Token::Atomic(inner) => Token::Atomic(Box::new(self.token(inner))),
+            Token::Lazy(quantifier) => match quantifier.repeat_range() {
+                Some((repeated, min, max)) => {
+                    let repeated = unwrap_group(self.token(repeated));
+                    // with a fixed count there is nothing to be lazy about  a{3}?
+                    if is_empty_group(&repeated) || max == Some(min) {
+                        return repeat(repeated, min, max);
+                    }
+                    Token::Lazy(Box::new(repeat(repeated, min, max)))
+                }
+                None => Token::Lazy(Box::new(self.token(quantifier))),
+            },
+            Token::OneORMore(_)
+            | Token::OneOrNone(_)
+            | Token::ZeroOrMore(_)
+            | Token::Repeat(..) => match token.repeat_range() {
+                Some((repeated, min, max)) => self.quantifier(repeated, min, max),
+                None => token.clone(),
+            },
+            _ => token.clone(),
+        }
+    }
+
+    // a greedy {min,max}, merged with a quantifier right inside it
+    fn quantifier(&self, repeated: &Token, min: usize, max: Option<usize>) -> Token {
+        let repeated = unwrap_group(self.token(repeated));
+        if is_empty_group(&repeated) {
+            return repeated;
+        }
+        // (?:a+)?  →  a*     (?:a?)+  →  a*     (?:a+)+  →  a+
+        if let Some((inner, inner_min, inner_max)) = repeated.repeat_range() {
+            if is_single_char(inner) && is_simple(min, max) && is_simple(inner_min, inner_max) {
+                let max = match (max, inner_max) {
+                    (Some(1), Some(1)) => Some(1),
+                    _ => None,
+                };
+                return repeat(inner.clone(), min * inner_min, max);
+            }
+        }
+        // (a+)+  →  (a+)  the group ends up with the same span
+        if let Token::Group(Group {
+            alternatives,
+            capture: Some(_),
+        }) = &repeated
+        {
+            if let [SubPattern { tokens }] = alternatives.as_slice() {
+                let same_quantifier = |token: &Token| {
+                    token
+                        .repeat_range()
+                        .is_some_and(|(inner, inner_min, inner_max)| {
+                            is_single_char(inner) && (inner_min, inner_max) == (min, max)
+                        })
+                };
+                if let [inner] = tokens.as_slice() {
+                    if !self.has_back_references && is_simple(min, max) && same_quantifier(inner) {
+                        return repeated;
+                    }
+                }
+            }
+        }
+        repeat(repeated, min, max)
+    }
+}
+
+// {min,max} written the simplest way:  a{0,1} → a?   a{1} → a   a{3} → aaa   a{0} → nothing
+fn repeat(token: Token, min: usize, max: Option<usize>) -> Token {
+    match (min, max) {
+        (0, Some(0)) => empty_group(),
+        (1, Some(1)) => token,
+        (0, Some(1)) => Token::OneOrNone(Box::new(token)),
+        (0, None) => Token::ZeroOrMore(Box::new(token)),
+        (1, None) => Token::OneORMore(Box::new(token)),
+        _ => match text(&token) {
+            Some(text) if max == Some(min) => Token::Text(text.repeat(min)),
+            _ => Token::Repeat(Box::new(token), min, max),
+        },
+    }
+}
+
+// ?  *  +, the quantifiers one can be merged into the other
+fn is_simple(min: usize, max: Option<usize>) -> bool {
+    matches!((min, max), (0, Some(1)) | (0, None) | (1, None))
+}
+
+// a token that always matches exactly one char, so repeating it can't capture anything
+fn is_single_char(token: &Token) -> bool {
+    match token {
+        Token::Literal(_)
+        | Token::WildCard
+        | Token::AnyChar
+        | Token::CharClass(_)
+        | Token::GroupClass(_) => true,
+        Token::CaseInsensitive(inner) => is_single_char(inner),
+        _ => false,
+    }
+}
+
+fn has_back_references(tokens: &[Token]) -> bool {
+    tokens.iter().any(|token| match token {
+        Token::BackReference(_) | Token::NamedBackReference(_) => true,
+        _ => token.children().into_iter().any(has_back_references),
+    })
+}
+
+fn empty_group() -> Token {
+    group(vec![vec![]])
+}
+
+fn is_empty_group(token: &Token) -> bool {
+    matches!(token, Token::Group(Group { alternatives, capture: None })
+        if alternatives.len() == 1 && alternatives[0].tokens.is_empty())
+}
+
+// (?:x)  →  x, for the token of a quantifier
+fn unwrap_group(token: Token) -> Token {
+    match token {
+        Token::Group(Group {
+            mut alternatives,
+            capture: None,
+        }) if alternatives.len() == 1 && alternatives[0].tokens.len() == 1 => {
+            alternatives[0].tokens.pop().unwrap()
+        }
+        token => token,
+    }
+}
+
+fn group(alternatives: Vec<Vec<Token>>) -> Token {
+    Token::Group(Group {
+        alternatives: alternatives
+            .into_iter()
+            .map(|tokens| SubPattern { tokens })
+            .collect(),
+        capture: None,
+    })
+}
+
+// ------------------------------------------------------------------------------//
+//                                    Literals                                   //
+// ------------------------------------------------------------------------------//
+
+// the text of a literal or of a run of them
+fn text(token: &Token) -> Option<String> {
+    match token {
+        Token::Literal(c) => Some(c.to_string()),
+        Token::Text(text) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+// one char stays a Literal, more become a Text
+fn text_token(text: &str) -> Token {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Token::Literal(c),
+        _ => Token::Text(text.to_string()),
+    }
+}
+
+// runs of literals become one Text, the same for runs of literals under (?i)
+fn merge_literals(tokens: Vec<Token>) -> Vec<Token> {
+    let mut merged: Vec<Token> = vec![];
+    for token in tokens {
+        let appended = merged
+            .last_mut()
+            .is_some_and(|last| append_literal(last, &token));
+        if !appended {
+            merged.push(token);
+        }
+    }
+    merged
+}
+
+fn append_literal(last: &mut Token, token: &Token) -> bool {
+    if let (Token::CaseInsensitive(last), Token::CaseInsensitive(token)) = (&mut *last, token) {
+        return append_literal(last, token);
+    }
+    match (text(last), text(token)) {
+        (Some(last_text), Some(text)) => {
+            *last = Token::Text(last_text + &text);
+            true
+        }
+        _ => false,
+    }
+}
+
+// ------------------------------------------------------------------------------//
+//                                   Factoring                                   //
+// ------------------------------------------------------------------------------//
+
+// the literal text an alternative starts with, empty when it starts with something else
+fn leading_text(tokens: &[Token]) -> String {
+    tokens.first().and_then(text).unwrap_or_default()
+}
+
+fn trailing_text(tokens: &[Token]) -> String {
+    tokens.last().and_then(text).unwrap_or_default()
+}
+
+// in bytes, on a char boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
+
+fn common_suffix_len(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
+
+// the alternative without the first `len` bytes of its leading text
+fn strip_prefix(mut tokens: Vec<Token>, len: usize) -> Vec<Token> {
+    let rest = leading_text(&tokens)[len..].to_string();
+    match rest.is_empty() {
+        true => tokens.remove(0),
+        false => std::mem::replace(&mut tokens[0], text_token(&rest)),
+    };
+    tokens
+}
+
+fn strip_suffix(mut tokens: Vec<Token>, len: usize) -> Vec<Token> {
+    let text = trailing_text(&tokens);
+    let rest = &text[..text.len() - len];
+    let last = tokens.len() - 1;
+    match rest.is_empty() {
+        true => tokens.remove(last),
+        false => std::mem::replace(&mut tokens[last], text_token(rest)),
+    };
+    tokens
+}
+
+// neighbours that start with the same text share it:  foo|foobar|fob|x → fo(?:o(?:|bar)|b)|x
+// only neighbours are merged, so the alternatives are still tried in the same order
+fn factor_prefixes(alternatives: Vec<Vec<Token>>, depth: usize) -> Vec<Vec<Token>> {
+    if alternatives.len() < 2 || depth == MAX_FACTOR_DEPTH {
+        return alternatives;
+    }
+    let mut factored = vec![];
+    let mut alternatives = alternatives.into_iter().peekable();
+    while let Some(first) = alternatives.next() {
+        let mut prefix = leading_text(&first);
+        let mut run = vec![first];
+        while let Some(next) = alternatives.peek() {
+            let len = common_prefix_len(&prefix, &leading_text(next));
+            if len == 0 {
+                break;
+            }
+            prefix.truncate(len);
+            run.extend(alternatives.next());
+        }
+        if run.len() == 1 {
+            factored.append(&mut run);
+            continue;
+        }
+        let rests = run
+            .into_iter()
+            .map(|tokens| strip_prefix(tokens, prefix.len()))
+            .collect();
+        factored.push(vec![
+            text_token(&prefix),
+            group(factor_prefixes(rests, depth + 1)),
+        ]);
+    }
+    factored
+}
+
+// alternatives that all end with the same text share it:  abc|xbc → (?:a|x)bc
+fn factor_suffix(alternatives: Vec<Vec<Token>>) -> Vec<Vec<Token>> {
+    let Some(first) = alternatives.first() else {
+        return alternatives;
+    };
+    let mut suffix = trailing_text(first);
+    for alternative in &alternatives[1..] {
+        let len = common_suffix_len(&suffix, &trailing_text(alternative));
+        suffix = suffix[suffix.len() - len..].to_string();
+    }
+    if alternatives.len() < 2 || suffix.is_empty() {
+        return alternatives;
+    }
+    let rests = alternatives
+        .into_iter()
+        .map(|tokens| strip_suffix(tokens, suffix.len()))
+        .collect();
+    vec![vec![group(rests), text_token(&suffix)]]
+}
+
+#[cfg(test)]
+use crate::Pattern::{Pattern, PatternOptions};
+
+#[cfg(test)]
+fn optimized(pattern: &str) -> Vec<Vec<Token>> {
+    let pattern: Pattern = pattern.parse().unwrap();
+    pattern
+        .optimized()
+        .iter()
+        .map(|sub_pattern| sub_pattern.tokens.clone())
+        .collect()
+}
+
+#[cfg(test)]
+fn text_of(text: &str) -> Token {
+    text_token(text)
+}
+
+#[test]
+fn test_merging_literals() {
+    assert_eq!(optimized("abc"), [[text_of("abc")]]);
+    assert_eq!(
+        optimized(r"ab\d(?:cd)e"),
+        [[
+            text_of("ab"),
+            Token::CharClass(crate::Pattern::CharClass::Digit),
+            text_of("cde")
+        ]]
+    );
+    // (?i) is already on every literal it applies to, the runs under it merge too
+    assert_eq!(
+        optimized("(?i)ab"),
+        [[Token::CaseInsensitive(Box::new(text_of("ab")))]]
+    );
+    assert_eq!(optimized("a(?:)[b]"), [[text_of("ab")]]);
+    assert!(Pattern::new(vec![]).optimized().is_empty());
+}
+
+#[test]
+fn test_factoring_alternatives() {
+    assert_eq!(
+        optimized("foo|foobar|fob"),
+        [[
+            text_of("fo"),
+            group(vec![
+                vec![text_of("o"), group(vec![vec![], vec![text_of("bar")]])],
+                vec![text_of("b")],
+            ]),
+        ]]
+    );
+    // only neighbours: moving `ab` next to `ac` would try it before `x`
+    assert_eq!(
+        optimized("ab|x|ac"),
+        [[text_of("ab")], [text_of("x")], [text_of("ac")]]
+    );
+    assert_eq!(
+        optimized("abc|xbc"),
+        [[
+            group(vec![vec![text_of("a")], vec![text_of("x")]]),
+            text_of("bc")
+        ]]
+    );
+    // a capture group keeps its span around the factored alternatives
+    let tokens = &optimized("(café|cafés)")[0];
+    let Token::Group(Group {
+        alternatives,
+        capture: Some(_),
+    }) = &tokens[0]
+    else {
+        panic!("{:?}", tokens);
+    };
+    assert_eq!(
+        alternatives[0].tokens,
+        [text_of("café"), group(vec![vec![], vec![text_of("s")]])]
+    );
+
+    // the same matches and captures as before
+    let pattern: Pattern = "x(foo|foobar|fob)y|(a|b)c".parse().unwrap();
+    let captures = pattern.captures("xfoobary").unwrap();
+    assert_eq!(captures.get(1).unwrap().range(), 1..7);
+    assert_eq!(pattern.find("zbc").unwrap().range(), 1..3);
+    let pattern: Pattern = "sam|samwise".parse().unwrap();
+    assert_eq!(pattern.find("samwise").unwrap().as_str(), "sam");
+    let longest = PatternOptions {
+        leftmost_longest: true,
+        ..Default::default()
+    };
+    let pattern = Pattern::with_options("sam|samwise", &longest).unwrap();
+    assert_eq!(pattern.find("samwise").unwrap().as_str(), "samwise");
+}
+
+#[test]
+fn test_simplifying_quantifiers() {
+    let a = || Box::new(Token::Literal('a'));
+    assert_eq!(optimized("(?:a+)+"), [[Token::OneORMore(a())]]);
+    assert_eq!(optimized("(?:a?)?"), [[Token::OneOrNone(a())]]);
+    assert_eq!(
+        optimized("(?:a+)?b"),
+        [[Token::ZeroOrMore(a()), text_of("b")]]
+    );
+    assert_eq!(
+        optimized("a{0,1}a{1,}"),
+        [[Token::OneOrNone(a()), Token::OneORMore(a())]]
+    );
+    assert_eq!(optimized("xa{3}?y[b]{2}"), [[text_of("xaaaybb")]]);
+    assert_eq!(optimized("a{1}b{0}(c){0}"), [[text_of("a")]]);
+    // a lazy quantifier that can vary stays lazy
+    assert_eq!(
+        optimized("a??"),
+        [[Token::Lazy(Box::new(Token::OneOrNone(a())))]]
+    );
+
+    // the group still captures, only once
+    let tokens = &optimized("(a+)+")[0];
+    assert!(matches!(&tokens[..], [Token::Group(_)]), "{:?}", tokens);
+    let pattern: Pattern = "(a+)+b".parse().unwrap();
+    assert_eq!(
+        pattern.captures("xaaab").unwrap().get(1).unwrap().range(),
+        1..4
+    );
+    // a backreference would see the last repetition
+    let tokens = &optimized(r"(a+)+\1")[0];
+    assert!(matches!(&tokens[0], Token::OneORMore(_)), "{:?}", tokens);
+    let pattern: Pattern = r"(a+)+:\1$".parse().unwrap();
+    assert_eq!(pattern.find("aaa:a").unwrap().range(), 0..5);
+}
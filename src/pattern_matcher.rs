@@ -1,162 +1,246 @@
-use crate::Pattern::Pattern;
+use std::ops::Range;
 
+use crate::aho_corasick::AhoCorasick;
+use crate::pattern_set::PatternSet;
+use crate::syntax::Syntax;
+use crate::utf8;
+use crate::Pattern::{MatchError, ParseError, Pattern, PatternOptions};
+
+// a pattern compiled once, then matched against as many lines as needed
+// it is Send + Sync, so one matcher can be shared by every file and thread of a search
+#[derive(Debug)]
 pub struct PatternMatcher {
-    pub input_line: String,
-    pub pattern: String,
+    engine: Engine,
+}
+
+#[derive(Debug)]
+enum Engine {
+    Pattern(Box<Pattern>),
+    // several patterns (-e p1 -e p2, -f), matched together in one pass
+    Set(PatternSet),
+    // fixed strings (-F), all looked for in one pass
+    Literals(AhoCorasick),
 }
+
 impl PatternMatcher {
-    pub fn default(&self) -> bool {
-        self.input_line.contains(&self.pattern)
+    pub fn new(pattern: &str) -> Result<Self, ParseError> {
+        Self::with_options(pattern, &PatternOptions::default())
+    }
+
+    pub fn with_options(pattern: &str, options: &PatternOptions) -> Result<Self, ParseError> {
+        Ok(PatternMatcher {
+            engine: Engine::Pattern(Box::new(Pattern::with_options(pattern, options)?)),
+        })
+    }
+
+    // a line matches when one of the patterns matches it
+    pub fn patterns<P: AsRef<str>>(
+        patterns: &[P],
+        options: &PatternOptions,
+    ) -> Result<Self, ParseError> {
+        match patterns {
+            [pattern] => Self::with_options(pattern.as_ref(), options),
+            _ => Ok(PatternMatcher {
+                engine: Engine::Set(PatternSet::with_options(patterns, options)?),
+            }),
+        }
+    }
+
+    // a line matches when it contains one of the literals, nothing in them is a metacharacter
+    // ignoring case is done by the automaton for ASCII literals, others need case folding
+    // so they go through a PatternSet of escaped literals
+    // either way the longest of the literals found at the leftmost place is the match
+    pub fn literals<L: AsRef<str>>(
+        literals: &[L],
+        options: &PatternOptions,
+    ) -> Result<Self, ParseError> {
+        let has_upper_case = || {
+            literals
+                .iter()
+                .any(|literal| literal.as_ref().chars().any(char::is_uppercase))
+        };
+        let case_insensitive = options.case_insensitive || options.smart_case && !has_upper_case();
+        let engine = if !case_insensitive {
+            Engine::Literals(AhoCorasick::new(&bytes_of(literals)))
+        } else if literals.iter().all(|literal| literal.as_ref().is_ascii()) {
+            Engine::Literals(AhoCorasick::ascii_case_insensitive(&bytes_of(literals)))
+        } else {
+            let escaped: Vec<String> = literals
+                .iter()
+                .map(|literal| escape(literal.as_ref()))
+                .collect();
+            let options = PatternOptions {
+                case_insensitive: true,
+                syntax: Syntax::Extended, // what escape writes for
+                leftmost_longest: true,   // like the automaton
+                ..options.clone()
+            };
+            Engine::Set(PatternSet::with_options(&escaped, &options)?)
+        };
+        Ok(PatternMatcher { engine })
     }
-    pub fn match_any_digit(&self) -> bool {
-        self.input_line.chars().any(|c| c.is_ascii_digit())
+
+    pub fn is_match(&self, input_line: &str) -> bool {
+        self.is_match_bytes(input_line.as_bytes())
+    }
+
+    // a line as it was read, it doesn't have to be valid UTF-8
+    // a search that goes past one of the Limits doesn't match, see try_is_match_bytes
+    pub fn is_match_bytes(&self, input_line: &[u8]) -> bool {
+        self.try_is_match_bytes(input_line).unwrap_or(false)
+    }
+
+    pub fn try_is_match_bytes(&self, input_line: &[u8]) -> Result<bool, MatchError> {
+        match &self.engine {
+            Engine::Pattern(pattern) => pattern.try_matches_bytes(input_line),
+            Engine::Set(set) => set.try_is_match_bytes(input_line),
+            Engine::Literals(automaton) => Ok(automaton.is_match(input_line)),
+        }
     }
-    pub fn match_non_specail_char(&self) -> bool {
-        self.input_line
-            .chars()
-            .any(|c| c.is_alphanumeric() || c == '_')
+
+    // which of the patterns (or literals) match the line, by their index in the list given
+    pub fn matching_patterns(&self, input_line: &[u8]) -> Vec<usize> {
+        match &self.engine {
+            Engine::Pattern(pattern) => match pattern.matches_bytes(input_line) {
+                true => vec![0],
+                false => vec![],
+            },
+            Engine::Set(set) => set.matches_bytes(input_line),
+            Engine::Literals(automaton) => automaton.matching_literals(input_line),
+        }
     }
-    pub fn match_character_class(&self) -> bool {
-        // get chars between [ ]   input [abc]  -> vec!['a','b','c']
-        let chars: Vec<char> = self.pattern[1..self.pattern.len() - 1].chars().collect();
-        self.input_line.chars().any(|c| chars.contains(&c)) // check if any char in input_line is in chars
+
+    // the spans of the matches in the line from left to right, they don't overlap (grep -o)
+    pub fn find_spans(&self, input_line: &[u8]) -> Vec<Range<usize>> {
+        self.try_find_spans(input_line).unwrap_or_default()
     }
-    pub fn match_all_the_class(&self) -> bool {
-        // get chars between [ ]   input [abc]  -> vec!['a','b','c']
-        let chars: Vec<char> = self.pattern[2..self.pattern.len() - 1].chars().collect();
-        !(self.input_line.chars().all(|c| chars.contains(&c))) // return flase if all char in input_line is in chars
+
+    pub fn try_find_spans(&self, input_line: &[u8]) -> Result<Vec<Range<usize>>, MatchError> {
+        let mut spans = vec![];
+        let mut start = 0;
+        while start <= input_line.len() {
+            let span = match &self.engine {
+                Engine::Pattern(pattern) => pattern
+                    .try_find_bytes_at(input_line, start)?
+                    .map(|found| found.range()),
+                Engine::Set(set) => set.try_find_bytes_at(input_line, start)?,
+                Engine::Literals(automaton) => automaton
+                    .find(&input_line[start..])
+                    .map(|(_, span)| start + span.start..start + span.end),
+            };
+            let Some(span) = span else {
+                break;
+            };
+            // after an empty match the next one starts a char further
+            start = match span.is_empty() {
+                true => span.end + utf8::decode(&input_line[span.end..]).map_or(1, |(_, len)| len),
+                false => span.end,
+            };
+            spans.push(span);
+        }
+        Ok(spans)
     }
 
-    pub fn match_pattern(&self) -> bool {
-        let pattern_engine: Pattern = self.pattern.parse().unwrap();
-        pattern_engine.matches(self.input_line.as_str())
+    // for the spans and captures of a match, None when there are several patterns
+    // or literals searched with Aho-Corasick
+    pub fn pattern(&self) -> Option<&Pattern> {
+        match &self.engine {
+            Engine::Pattern(pattern) => Some(pattern),
+            Engine::Set(_) | Engine::Literals(_) => None,
+        }
     }
 }
 
-pub fn match_input(input_line: &str, pattern: &str) -> bool {
-    let matcher = PatternMatcher {
-        input_line: input_line.to_string(),
-        pattern: pattern.to_string(),
-    };
-    if pattern.chars().count() == 1 {
-        matcher.default()
-    } else if pattern == "\\d" {
-        matcher.match_any_digit()
-    } else if pattern == "\\w" {
-        matcher.match_non_specail_char()
-    } else if pattern.starts_with('[') && pattern.ends_with(']') && pattern.chars().count() > 2 {
-        if pattern.chars().nth(1).unwrap() == '^' {
-            matcher.match_all_the_class()
-        } else {
-            matcher.match_character_class()
+fn bytes_of<L: AsRef<str>>(literals: &[L]) -> Vec<&[u8]> {
+    literals
+        .iter()
+        .map(|literal| literal.as_ref().as_bytes())
+        .collect()
+}
+
+// the pattern that matches `literal` as it is written: a \ before every ASCII punctuation
+pub fn escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
         }
-    } else {
-        matcher.match_pattern()
+        escaped.push(c);
     }
+    escaped
+}
+
+// for a one-off check, build a PatternMatcher once to match many lines
+pub fn match_input(input_line: &str, pattern: &str) -> Result<bool, ParseError> {
+    Ok(PatternMatcher::new(pattern)?.is_match(input_line))
 }
 
 #[test]
 fn test_match_pattern_on_literals() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "abc".to_string(),
-        input_line: "abc".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("abc").unwrap();
+    assert_eq!(pattern_matcher.is_match("abc"), true);
 }
 #[test]
 fn test_match_pattern_on_digits() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "\\d\\d".to_string(),
-        input_line: "12".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("\\d\\d").unwrap();
+    assert_eq!(pattern_matcher.is_match("12"), true);
 }
 #[test]
 fn test_match_pattern_on_identifier() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "\\w\\w\\w".to_string(),
-        input_line: "a_Z".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("\\w\\w\\w").unwrap();
+    assert_eq!(pattern_matcher.is_match("a_Z"), true);
 }
 
 #[test]
 fn test_match_pattern_on_sol() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "^abc\\d\\wfg\\d".to_string(),
-        input_line: "abc5_fg5".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("^abc\\d\\wfg\\d").unwrap();
+    assert_eq!(pattern_matcher.is_match("abc5_fg5"), true);
 }
 #[test]
 fn test_match_pattern_on_eol() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "abc\\d\\wfg\\d$".to_string(),
-        input_line: "sadasd135abc5_fg5".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("abc\\d\\wfg\\d$").unwrap();
+    assert_eq!(pattern_matcher.is_match("sadasd135abc5_fg5"), true);
 }
 
 #[test]
 fn test_match_pattern_on_combinations() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "ac\\ddg\\w\\wf\\w".to_string(),
-        input_line: "ac5dga_fW".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("ac\\ddg\\w\\wf\\w").unwrap();
+    assert_eq!(pattern_matcher.is_match("ac5dga_fW"), true);
 }
 #[test]
 fn test_match_pattern_on_exact_match() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "^strawberry$".to_string(),
-        input_line: "strawberry".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("^strawberry$").unwrap();
+    assert_eq!(pattern_matcher.is_match("strawberry"), true);
 }
 
 #[test]
 fn test_match_pattern_on_one_or_more() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "ca+t".to_string(),
-        input_line: "act".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), false);
+    let pattern_matcher = PatternMatcher::new("ca+t").unwrap();
+    assert_eq!(pattern_matcher.is_match("act"), false);
 }
 
 #[test]
 fn test_match_pattern_on_one_or_none() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "ca?at".to_string(),
-        input_line: "cat".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("ca?at").unwrap();
+    assert_eq!(pattern_matcher.is_match("cat"), true);
 }
 
 #[test]
 fn test_match_pattern_on_wild_card() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "g.+gol".to_string(),
-        input_line: "goøö0Ogol".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("g.+gol").unwrap();
+    assert_eq!(pattern_matcher.is_match("goøö0Ogol"), true);
 }
 
 #[test]
 fn test_match_pattern_on_alternation() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "(cat|dog)".to_string(),
-        input_line: "cat".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("(cat|dog)").unwrap();
+    assert_eq!(pattern_matcher.is_match("cat"), true);
 }
 
 #[test]
 fn test_match_pattern_on_one_or_more_digit() {
-    let pattern_matcher = PatternMatcher {
-        pattern: "^I see \\d+ (cat|dog)s?$".to_string(),
-        input_line: "I see 42 dogs".to_string(),
-    };
-    assert_eq!(pattern_matcher.match_pattern(), true);
+    let pattern_matcher = PatternMatcher::new("^I see \\d+ (cat|dog)s?$").unwrap();
+    assert_eq!(pattern_matcher.is_match("I see 42 dogs"), true);
 }
 #[test]
 fn test_trigger_hiiii() {
@@ -164,3 +248,496 @@ fn test_trigger_hiiii() {
     let parsed: Pattern = s.parse().unwrap();
     assert!(parsed.matches("aaa"));
 }
+
+#[test]
+fn test_match_pattern_on_class_inside_pattern() {
+    let parsed: Pattern = "a[bc]d".parse().unwrap();
+    assert!(parsed.matches("xacd"));
+    assert!(!parsed.matches("aed"));
+    assert!(match_input("log: abd", "a[bc]d").unwrap());
+}
+
+#[test]
+fn test_match_pattern_on_class_ranges_and_quantifiers() {
+    let parsed: Pattern = r"^[a-z0-9\-]+$".parse().unwrap();
+    assert!(parsed.matches("build-42"));
+    assert!(!parsed.matches("Build-42"));
+    assert!(match_input("id=7", "[0-9]").unwrap());
+    assert!(!match_input("abc", "[^a-c]").unwrap());
+}
+
+#[test]
+fn test_match_pattern_on_posix_classes() {
+    let parsed: Pattern = "[[:alpha:]]+[[:space:]][[:digit:]]".parse().unwrap();
+    assert!(parsed.matches("error 5"));
+    assert!(!parsed.matches("error5"));
+}
+
+#[test]
+fn test_match_pattern_on_nested_groups() {
+    let parsed: Pattern = "x((a|b)c|d)+y".parse().unwrap();
+    assert!(parsed.matches("xacdbcy"));
+    assert!(parsed.matches("xdy"));
+    assert!(!parsed.matches("xaby"));
+}
+
+#[test]
+fn test_match_pattern_on_groups_with_anchors() {
+    let parsed: Pattern = "(^foo|bar$)".parse().unwrap();
+    assert!(parsed.matches("foo at the start"));
+    assert!(parsed.matches("ends with bar"));
+    assert!(!parsed.matches("a foo and a bar too"));
+}
+
+#[test]
+fn test_match_pattern_on_many_alternations() {
+    let pattern = "(a|b)".repeat(40);
+    let parsed: Pattern = pattern.parse().unwrap();
+    assert!(parsed.matches(&"ab".repeat(20)));
+    assert!(!parsed.matches(&"ab".repeat(19)));
+}
+
+#[test]
+fn test_match_pattern_on_back_references() {
+    let repeated_word: Pattern = r"(\w+) \1".parse().unwrap();
+    assert!(repeated_word.matches("one two two three"));
+    assert!(!repeated_word.matches("one two three"));
+
+    let tags: Pattern = r"<(?<tag>\w+)>[^<]+</\k<tag>>".parse().unwrap();
+    assert!(tags.matches("<b>bold</b>"));
+    assert!(!tags.matches("<b>bold</i>"));
+}
+
+#[test]
+fn test_match_pattern_on_back_references_with_quantifiers_and_alternation() {
+    // the reference has to backtrack into the group to find "ab" repeated
+    let parsed: Pattern = r"^(a|ab)(c|bcd)?\1+$".parse().unwrap();
+    assert!(parsed.matches("ababab"));
+    assert!(!parsed.matches("ababa"));
+
+    let parsed: Pattern = r"(x)?y\1".parse().unwrap();
+    assert!(parsed.matches("xyx"));
+    assert!(!parsed.matches("yx"));
+}
+
+#[test]
+fn test_match_pattern_on_zero_or_more() {
+    let parsed: Pattern = "^ab*c$".parse().unwrap();
+    assert!(parsed.matches("ac"));
+    assert!(parsed.matches("abbbc"));
+    assert!(!parsed.matches("abxc"));
+
+    let parsed: Pattern = r"^(ab)*\d*$".parse().unwrap();
+    assert!(parsed.matches("abab12"));
+    assert!(parsed.matches("12"));
+    assert!(!parsed.matches("aba12"));
+}
+
+#[test]
+fn test_match_pattern_on_counted_repetition() {
+    let exact: Pattern = r"^\d{3}-[a-z]{2}$".parse().unwrap();
+    assert!(exact.matches("123-ab"));
+    assert!(!exact.matches("12-ab"));
+    assert!(!exact.matches("1234-ab"));
+
+    let at_least: Pattern = "^(ha){2,}!$".parse().unwrap();
+    assert!(at_least.matches("hahaha!"));
+    assert!(!at_least.matches("ha!"));
+
+    let between: Pattern = "^x{1,3}y$".parse().unwrap();
+    assert!(between.matches("xxxy"));
+    assert!(!between.matches("xxxxy"));
+
+    // an optional group inside a count still has to reach the count
+    let parsed: Pattern = "^(a?){3}b$".parse().unwrap();
+    assert!(parsed.matches("ab"));
+    assert!(parsed.matches("b"));
+}
+
+#[test]
+fn test_match_pattern_on_possessive_quantifiers() {
+    // a++ keeps every 'a', so nothing is left for the last one
+    let parsed: Pattern = "^a++a".parse().unwrap();
+    assert!(!parsed.matches("aaa"));
+
+    let parsed: Pattern = r"^\d*+x".parse().unwrap();
+    assert!(parsed.matches("123x"));
+    assert!(!parsed.matches("123"));
+}
+
+#[test]
+fn test_match_pattern_on_atomic_groups() {
+    let atomic: Pattern = "^(?>a|ab)c".parse().unwrap();
+    assert!(atomic.matches("ac"));
+    assert!(!atomic.matches("abc"));
+
+    let backtracking: Pattern = "^(?:a|ab)c".parse().unwrap();
+    assert!(backtracking.matches("abc"));
+}
+
+#[test]
+fn test_match_pattern_on_word_boundaries() {
+    let parsed: Pattern = r"\bfoo\b".parse().unwrap();
+    assert!(parsed.matches("a foo here"));
+    assert!(parsed.matches("foo"));
+    assert!(!parsed.matches("food"));
+    assert!(!parsed.matches("a_foo"));
+
+    let parsed: Pattern = r"\Boo\B".parse().unwrap();
+    assert!(parsed.matches("food"));
+    assert!(!parsed.matches("oo"));
+}
+
+#[test]
+fn test_match_pattern_on_anchors_inside_groups() {
+    let parsed: Pattern = "(^#|;$)".parse().unwrap();
+    assert!(parsed.matches("# comment"));
+    assert!(parsed.matches("x = 1;"));
+    assert!(!parsed.matches("x = 1; # no"));
+
+    // anchors under quantifiers and in the middle of a pattern
+    let parsed: Pattern = "^(ab$|a)+b".parse().unwrap();
+    assert!(parsed.matches("aab"));
+    assert!(!parsed.matches("cab"));
+    let parsed: Pattern = r"\Aa+\z".parse().unwrap();
+    assert!(parsed.matches("aaa"));
+    assert!(!parsed.matches("aaab"));
+}
+
+#[test]
+fn test_match_pattern_on_escaped_dots() {
+    let parsed: Pattern = r"^10\.0\.0\.1$".parse().unwrap();
+    assert!(parsed.matches("10.0.0.1"));
+    assert!(!parsed.matches("10a0b0c1"));
+}
+
+#[test]
+fn test_match_pattern_on_negated_and_space_classes() {
+    let parsed: Pattern = r"^\S+\s+\D\W$".parse().unwrap();
+    assert!(parsed.matches("key \tx!"));
+    assert!(!parsed.matches("key 1!"));
+    assert!(!parsed.matches("key xy"));
+
+    let parsed: Pattern = r"a\tb\x2ec".parse().unwrap();
+    assert!(parsed.matches("a\tb.c"));
+    assert!(!parsed.matches("a b.c"));
+}
+
+#[test]
+fn test_match_pattern_on_unicode_classes() {
+    let parsed: Pattern = r"^\w+\s\d+$".parse().unwrap();
+    assert!(parsed.matches("café\u{3000}٣٤"));
+    assert!(parsed.matches("привет_1 42"));
+
+    let parsed: Pattern = r"\bжук\b".parse().unwrap();
+    assert!(parsed.matches("это жук."));
+    assert!(!parsed.matches("жуки"));
+
+    let options = PatternOptions {
+        ascii_only: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options(r"^\w+$", &options).unwrap();
+    assert!(parsed.matches("cafe_1"));
+    assert!(!parsed.matches("café"));
+    let parsed = Pattern::with_options(r"\d", &options).unwrap();
+    assert!(!parsed.matches("٣"));
+
+    // the \d shortcut agrees with the engine
+    assert!(match_input("٣", r"\d").unwrap());
+}
+
+#[test]
+fn test_match_pattern_on_unicode_properties() {
+    let parsed: Pattern = r"^\p{Greek}+ \p{Lu}\P{L}$".parse().unwrap();
+    assert!(parsed.matches("αβγ Ж7"));
+    assert!(!parsed.matches("abc Ж7"));
+    assert!(!parsed.matches("αβγ жx"));
+
+    let parsed: Pattern = r"[\p{Han}\p{Hiragana}]+".parse().unwrap();
+    assert_eq!(
+        parsed.find("say こんにちは世界!").unwrap().as_str(),
+        "こんにちは世界"
+    );
+}
+
+#[test]
+fn test_match_pattern_ignoring_case() {
+    let parsed: Pattern = "(?i)hello [a-z]+!".parse().unwrap();
+    assert!(parsed.matches("HeLLo WORLD!"));
+
+    // Unicode simple case folding: the Kelvin sign is a k, σ and ς are Σ
+    let parsed: Pattern = "(?i)^(?:kelvin|ΣΟΦΟΣ)$".parse().unwrap();
+    assert!(parsed.matches("\u{212a}ELVIN"));
+    assert!(parsed.matches("σοφος"));
+    assert!(!parsed.matches("σοφoς"));
+
+    // a negated class leaves out every case of what it lists
+    let parsed: Pattern = "(?i)^[^a]$".parse().unwrap();
+    assert!(!parsed.matches("A"));
+    assert!(parsed.matches("b"));
+    let parsed: Pattern = r"(?i)^\p{Lu}+$".parse().unwrap();
+    assert!(parsed.matches("abc"));
+
+    let parsed: Pattern = r"(?i)\b(\w+) \1\b".parse().unwrap();
+    assert!(parsed.matches("say Hello HELLO"));
+    assert!(!parsed.matches("say Hello Help"));
+}
+
+#[test]
+fn test_match_pattern_with_scoped_flags() {
+    let parsed: Pattern = "^(?i:a)b$".parse().unwrap();
+    assert!(parsed.matches("Ab"));
+    assert!(!parsed.matches("AB"));
+
+    // the flag lasts until the end of its group, across the alternatives
+    let parsed: Pattern = "^(x(?i)y|z)$".parse().unwrap();
+    assert!(parsed.matches("xY"));
+    assert!(parsed.matches("Z"));
+    assert!(!parsed.matches("XY"));
+
+    let options = PatternOptions {
+        case_insensitive: true,
+        ..Default::default()
+    };
+    let parsed = Pattern::with_options("^a(?-i)b$", &options).unwrap();
+    assert!(parsed.matches("Ab"));
+    assert!(!parsed.matches("AB"));
+}
+
+#[test]
+fn test_match_with_smart_case() {
+    let smart_case = PatternOptions {
+        smart_case: true,
+        ..Default::default()
+    };
+    let matcher = |pattern| PatternMatcher::with_options(pattern, &smart_case).unwrap();
+    assert!(matcher("error").is_match("Error: disk"));
+    assert!(!matcher("Error").is_match("error: disk"));
+    assert!(matcher("e").is_match("ERROR"));
+}
+
+#[test]
+fn test_match_pattern_with_look_aheads() {
+    let parsed: Pattern = r"foo(?!bar)".parse().unwrap();
+    assert!(parsed.matches("foobaz"));
+    assert!(parsed.matches("foo"));
+    assert!(!parsed.matches("foobar"));
+
+    let parsed: Pattern = r"^(?=.*\d)(?=.*[a-z])\w{6,}$".parse().unwrap();
+    assert!(parsed.matches("abc123"));
+    assert!(!parsed.matches("abcdef"));
+    assert!(!parsed.matches("ab1"));
+
+    // what a positive lookahead captured is kept
+    let parsed: Pattern = r"(?=(\w+))\w".parse().unwrap();
+    let captures = parsed.captures("hey").unwrap();
+    assert_eq!(captures.get(0).unwrap().as_str(), "h");
+    assert_eq!(captures.get(1).unwrap().as_str(), "hey");
+}
+
+#[test]
+fn test_match_pattern_with_look_behinds() {
+    let parsed: Pattern = r"(?<=\$)\d+".parse().unwrap();
+    assert_eq!(parsed.find("cost: 10 or $25").unwrap().as_str(), "25");
+    assert!(!parsed.matches("cost: 25"));
+
+    let parsed: Pattern = r"(?<!-)\b\d+".parse().unwrap();
+    assert_eq!(parsed.find("-4 and 7").unwrap().as_str(), "7");
+
+    // alternatives of different lengths, and multi-byte chars before the position
+    let parsed: Pattern = r"(?<=€|EUR )\d+".parse().unwrap();
+    assert_eq!(parsed.find("€12").unwrap().as_str(), "12");
+    assert_eq!(parsed.find("EUR 30").unwrap().as_str(), "30");
+    assert!(!parsed.matches("USD 30"));
+
+    let parsed: Pattern = r"^(?<=a)".parse().unwrap();
+    assert!(!parsed.matches("a"));
+}
+
+#[test]
+fn test_match_pattern_on_wildcard_and_literal_dots() {
+    let parsed: Pattern = "^a.c$".parse().unwrap();
+    assert!(parsed.matches("abc"));
+    assert!(parsed.matches("a.c"));
+    assert!(!parsed.matches("a\nc"));
+
+    let parsed: Pattern = "^a(?s).c$".parse().unwrap();
+    assert!(parsed.matches("a\nc"));
+
+    let parsed: Pattern = r"^a[.]c\.$".parse().unwrap();
+    assert!(parsed.matches("a.c."));
+    assert!(!parsed.matches("abc."));
+    assert!(!parsed.matches("a.cx"));
+
+    // a pattern that is only a dot is still the wildcard
+    assert!(match_input("x", ".").unwrap());
+    assert!(!match_input("", ".").unwrap());
+    assert!(match_input("a", "a").unwrap());
+}
+
+#[test]
+fn test_pattern_matcher_is_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<PatternMatcher>();
+
+    let matcher = PatternMatcher::new(r"^\w+ (\d+)$").unwrap();
+    let lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+    let matched = std::thread::scope(|scope| {
+        let workers: Vec<_> = lines
+            .chunks(10)
+            .map(|chunk| scope.spawn(|| chunk.iter().filter(|l| matcher.is_match(l)).count()))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum::<usize>()
+    });
+    assert_eq!(matched, 100);
+    assert!(!matcher.is_match("line x"));
+    let captures = matcher.pattern().unwrap().captures("line 42").unwrap();
+    assert_eq!(captures.get(1).unwrap().as_str(), "42");
+}
+
+#[test]
+fn test_match_lines_that_are_not_utf8() {
+    // "café: timeout" and "déjà vu" written in Latin-1
+    let matcher = PatternMatcher::new(r"^caf.: \w+$").unwrap();
+    assert!(matcher.is_match_bytes(b"caf\xe9: timeout"));
+    assert!(!matcher.is_match_bytes(b"caf\xe9\xe9: timeout"));
+
+    // a stray byte is one char that only . and negated classes accept
+    let matcher = PatternMatcher::new(r"d[^a-z]j. vu").unwrap();
+    assert!(matcher.is_match_bytes(b"d\xe9j\xe0 vu"));
+    let matcher = PatternMatcher::new(r"\bvu\b").unwrap();
+    assert!(matcher.is_match_bytes(b"d\xe9j\xe0 vu"));
+    // \xe0 in a pattern is the char à, not the byte 0xe0
+    let matcher = PatternMatcher::new(r"(?<=\xe0)").unwrap();
+    assert!(!matcher.is_match_bytes(b"d\xe9j\xe0 vu"));
+}
+
+#[test]
+fn test_match_fixed_strings() {
+    let options = PatternOptions::default();
+    let matcher = PatternMatcher::literals(&["a.b", "(x)", "[1]"], &options).unwrap();
+    assert!(matcher.is_match("1 (x) 2"));
+    assert!(matcher.is_match("list[1]"));
+    assert!(!matcher.is_match("axb x 1"));
+    assert!(matcher.pattern().is_none());
+
+    let words: Vec<String> = (0..3000).map(|n| format!("id-{}.", n)).collect();
+    let matcher = PatternMatcher::literals(&words, &options).unwrap();
+    assert!(matcher.is_match("see id-2999. now"));
+    assert!(!matcher.is_match("id-3000."));
+}
+
+#[test]
+fn test_match_fixed_strings_ignoring_case() {
+    let ignore_case = PatternOptions {
+        case_insensitive: true,
+        ..Default::default()
+    };
+    let matcher = PatternMatcher::literals(&["error+"], &ignore_case).unwrap();
+    assert!(matcher.is_match("ERROR+ 1"));
+    assert!(!matcher.is_match("ERRORR"));
+    // not ASCII, so the escaped literals go through a Pattern with Unicode case folding
+    let matcher = PatternMatcher::literals(&["straße?", "ΣΊΣΥΦΟΣ"], &ignore_case).unwrap();
+    assert!(matcher.is_match("STRAẞE?"));
+    assert!(matcher.is_match("σίσυφος"));
+    assert!(!matcher.is_match("strasse"));
+
+    let smart_case = PatternOptions {
+        smart_case: true,
+        ..Default::default()
+    };
+    let matcher = PatternMatcher::literals(&["warn", "Error"], &smart_case).unwrap();
+    assert!(!matcher.is_match("WARN"));
+    assert!(matcher.is_match("Error"));
+}
+
+#[test]
+fn test_match_basic_regular_expressions() {
+    let basic = PatternOptions {
+        syntax: Syntax::Basic,
+        ..Default::default()
+    };
+    let matcher = PatternMatcher::with_options(r"^\(ab\)\{2\}\(c\|d\)$", &basic).unwrap();
+    assert!(matcher.is_match("ababd"));
+    assert!(!matcher.is_match("abd"));
+    // the extended operators are plain chars
+    let matcher = PatternMatcher::with_options("a+(b)?|c", &basic).unwrap();
+    assert!(matcher.is_match("xa+(b)?|cx"));
+    assert!(!matcher.is_match("aab"));
+    let matcher = PatternMatcher::with_options(r"*x\+", &basic).unwrap();
+    assert!(matcher.is_match("*xxx"));
+    assert!(!matcher.is_match("xxx"));
+}
+
+#[test]
+fn test_find_spans() {
+    let matcher = PatternMatcher::new(r"\d+").unwrap();
+    assert_eq!(matcher.find_spans(b"a1 22 b333"), [1..2, 3..5, 7..10]);
+    let matcher = PatternMatcher::new(r"x*").unwrap();
+    assert_eq!(matcher.find_spans("éxx".as_bytes()), [0..0, 2..4, 4..4]);
+
+    let longest = PatternOptions {
+        leftmost_longest: true,
+        ..Default::default()
+    };
+    let matcher = PatternMatcher::with_options("ab|abcd|c", &longest).unwrap();
+    assert_eq!(matcher.find_spans(b"abcd abc"), [0..4, 5..7, 7..8]);
+    let matcher = PatternMatcher::literals(&["ab", "abcd", "c"], &longest).unwrap();
+    assert_eq!(matcher.find_spans(b"abcd abc"), [0..4, 5..7, 7..8]);
+
+    // -F picks the same matches with or without a literal that isn't ASCII
+    let ignore_case = PatternOptions {
+        case_insensitive: true,
+        ..Default::default()
+    };
+    for literals in [&["ab", "abcd"][..], &["ab", "abcd", "é"]] {
+        let matcher = PatternMatcher::literals(literals, &ignore_case).unwrap();
+        assert_eq!(matcher.find_spans(b"xABCD Ab"), [1..5, 6..8]);
+        let matcher = PatternMatcher::literals(literals, &PatternOptions::default()).unwrap();
+        assert_eq!(matcher.find_spans(b"xabcd ab"), [1..5, 6..8]);
+    }
+}
+
+#[test]
+fn test_match_several_patterns() {
+    let options = PatternOptions::default();
+    let matcher = PatternMatcher::patterns(&[r"^\d+$", "err(or)?", r"(\d)\1"], &options).unwrap();
+    assert!(matcher.is_match("fatal err"));
+    assert_eq!(matcher.matching_patterns(b"error 42"), [1]);
+    assert_eq!(matcher.matching_patterns(b"1000"), [0, 2]);
+    assert!(matcher.matching_patterns(b"ok").is_empty());
+    assert_eq!(matcher.find_spans(b"11 err"), [0..2, 3..6]);
+
+    let matcher = PatternMatcher::literals(&["cat", "at", "dog"], &options).unwrap();
+    assert_eq!(matcher.matching_patterns(b"a cat"), [0, 1]);
+    let matcher = PatternMatcher::literals(&["abc", "abc"], &options).unwrap();
+    assert_eq!(matcher.matching_patterns(b"abc"), [0, 1]);
+}
+
+#[test]
+fn test_match_empty_patterns() {
+    let options = PatternOptions::default();
+    let matcher = PatternMatcher::new("").unwrap();
+    assert!(matcher.is_match(""));
+    assert!(matcher.is_match("abc"));
+    assert_eq!(matcher.find_spans(b"ab"), [0..0, 1..1, 2..2]);
+    let matcher = PatternMatcher::literals(&[""], &options).unwrap();
+    assert!(matcher.is_match("abc"));
+    // an empty line in -f matches every line
+    let matcher = PatternMatcher::patterns(&["x", ""], &options).unwrap();
+    assert_eq!(matcher.matching_patterns(b"a"), [1]);
+
+    let basic = PatternOptions {
+        syntax: Syntax::Basic,
+        ..Default::default()
+    };
+    let matcher = PatternMatcher::with_options("^$", &basic).unwrap();
+    assert!(matcher.is_match(""));
+    assert!(!matcher.is_match(" "));
+    let matcher = PatternMatcher::with_options(r"\(a\|\)b", &basic).unwrap();
+    assert!(matcher.is_match("b"));
+}
@@ -0,0 +1,249 @@
+use std::ops::Range;
+
+use crate::limits::Limits;
+use crate::nfa::Program;
+use crate::Pattern::{MatchError, MatchState, ParseError, Pattern, PatternOptions};
+
+// ------------------------------------------------------------------------------//
+//                                  Pattern Set                                  //
+// ------------------------------------------------------------------------------//
+
+// many patterns matched together: they are compiled into one program, and one pass of the
+// Pike VM over a line tells which of them match it (the rules of a rule file that fired)
+#[derive(Debug)]
+pub struct PatternSet {
+    patterns: Vec<Pattern>,
+    program: Option<Program>,
+    // the index in the set of the pattern behind each Match(i) of the program
+    in_program: Vec<usize>,
+    // backreferences, lookarounds and atomic groups need the backtracker, one pattern at a time
+    backtracked: Vec<usize>,
+    limits: Limits, // for the pass of the program, the patterns have theirs
+    leftmost_longest: bool,
+}
+
+impl PatternSet {
+    pub fn new<P: AsRef<str>>(patterns: &[P]) -> Result<Self, ParseError> {
+        Self::with_options(patterns, &PatternOptions::default())
+    }
+
+    pub fn with_options<P: AsRef<str>>(
+        patterns: &[P],
+        options: &PatternOptions,
+    ) -> Result<Self, ParseError> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Pattern::with_options(pattern.as_ref(), options))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (backtracked, in_program): (Vec<usize>, Vec<usize>) =
+            (0..patterns.len()).partition(|index| patterns[*index].needs_backtracking());
+        let program = match in_program.is_empty() {
+            true => None,
+            false => {
+                let sub_patterns: Vec<_> = in_program
+                    .iter()
+                    .map(|index| patterns[*index].optimized())
+                    .collect();
+                let group_count = in_program
+                    .iter()
+                    .map(|index| patterns[*index].group_count())
+                    .max()
+                    .unwrap_or(0);
+                Program::compile_set(&sub_patterns, group_count)
+            }
+        };
+        Ok(PatternSet {
+            patterns,
+            program,
+            in_program,
+            backtracked,
+            limits: options.limits,
+            leftmost_longest: options.leftmost_longest,
+        })
+    }
+
+    // the indices of the patterns that match somewhere in the input, in increasing order
+    pub fn matches(&self, input: &str) -> Vec<usize> {
+        self.matches_bytes(input.as_bytes())
+    }
+
+    // a pattern whose search goes past one of the Limits doesn't match, see try_is_match_bytes
+    // (the ones the pass of the program found before it stopped do)
+    pub fn matches_bytes(&self, input: &[u8]) -> Vec<usize> {
+        let mut matched = vec![false; self.in_program.len()];
+        let _ = self.search_program(input, &mut matched);
+        let mut indices: Vec<usize> = self
+            .in_program
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| *matched)
+            .map(|(index, _)| *index)
+            .collect();
+        indices.extend(
+            self.backtracked
+                .iter()
+                .filter(|index| self.patterns[**index].matches_bytes(input)),
+        );
+        indices.sort_unstable();
+        indices
+    }
+
+    pub fn is_match_bytes(&self, input: &[u8]) -> bool {
+        !self.matches_bytes(input).is_empty()
+    }
+
+    // going past the step or recursion limit is an error, unless a match was found before
+    pub fn try_is_match_bytes(&self, input: &[u8]) -> Result<bool, MatchError> {
+        let mut matched = vec![false; self.in_program.len()];
+        let searched = self.search_program(input, &mut matched);
+        if matched.contains(&true) {
+            return Ok(true);
+        }
+        searched?;
+        for index in &self.backtracked {
+            if self.patterns[*index].try_matches_bytes(input)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // the leftmost match of any pattern starting at `start` or later, like the match of the
+    // patterns joined with | : on a tie the first pattern wins, or the longest match
+    // with leftmost_longest
+    pub fn find_bytes_at(&self, input: &[u8], start: usize) -> Option<Range<usize>> {
+        self.try_find_bytes_at(input, start).ok()?
+    }
+
+    // one pass of the program finds the leftmost match of its patterns, the backtracked
+    // ones are searched one at a time
+    pub fn try_find_bytes_at(
+        &self,
+        input: &[u8],
+        start: usize,
+    ) -> Result<Option<Range<usize>>, MatchError> {
+        // the index of the pattern that found it, and its span
+        let mut found: Option<(usize, Range<usize>)> = None;
+        if let Some(program) = &self.program {
+            let mut state = MatchState::new(input, 0).with_limits(&self.limits);
+            let pattern = program.search_leftmost(&mut state, start, self.leftmost_longest);
+            if let (Some(pattern), Some((from, to))) = (state.result(pattern)?, state.slots[0]) {
+                found = Some((self.in_program[pattern], from..to));
+            }
+        }
+        for index in &self.backtracked {
+            let pattern = &self.patterns[*index];
+            let Some(span) = pattern.try_find_bytes_at(input, start)?.map(|m| m.range()) else {
+                continue;
+            };
+            let better = match &found {
+                Some((found_index, found)) if self.leftmost_longest => {
+                    (span.start, std::cmp::Reverse(span.end), index)
+                        < (found.start, std::cmp::Reverse(found.end), found_index)
+                }
+                Some((found_index, found)) => (span.start, index) < (found.start, found_index),
+                None => true,
+            };
+            if better {
+                found = Some((*index, span));
+            }
+        }
+        Ok(found.map(|(_, span)| span))
+    }
+
+    // one pass of the program, `matched` says which of the patterns in it match
+    fn search_program(&self, input: &[u8], matched: &mut [bool]) -> Result<(), MatchError> {
+        let Some(program) = &self.program else {
+            return Ok(());
+        };
+        let mut state = MatchState::new(input, 0).with_limits(&self.limits);
+        program.search_set(&mut state, matched);
+        state.result(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+}
+
+#[test]
+fn test_pattern_set_reports_every_pattern_that_matches() {
+    let set = PatternSet::new(&[r"\bERROR\b", r"\d{3}", "^warn", "timeout$", "x+y"]).unwrap();
+    assert_eq!(set.matches("ERROR 500 after timeout"), [0, 1, 3]);
+    assert_eq!(set.matches("warn: 12"), [2]);
+    assert_eq!(set.matches("xxy ERRORS"), [4]);
+    assert!(set.matches("nothing here").is_empty());
+    assert!(set.program.is_some() && set.backtracked.is_empty());
+}
+
+#[test]
+fn test_pattern_set_with_backtracked_patterns() {
+    let set = PatternSet::new(&[r"(\w)\1", "cat", r"foo(?!bar)", "dog"]).unwrap();
+    assert_eq!(set.backtracked, [0, 2]);
+    assert_eq!(set.matches("a cat and a dog"), [1, 3]);
+    assert_eq!(set.matches("foobar food"), [0, 2]);
+    assert_eq!(set.matches("foobar"), [0]);
+    assert!(set.matches("fobar").is_empty());
+}
+
+#[test]
+fn test_pattern_set_with_many_patterns() {
+    let patterns: Vec<String> = (0..300).map(|n| format!(r"rule{}:\d+", n)).collect();
+    let set = PatternSet::new(&patterns).unwrap();
+    assert_eq!(set.len(), 300);
+    assert_eq!(set.matches("rule7:1 rule299:42 rule12:x"), [7, 299]);
+    assert_eq!(set.find_bytes_at(b"rule12:x rule299:42", 0), Some(9..19));
+}
+
+#[test]
+fn test_pattern_set_step_limit() {
+    let options = PatternOptions {
+        limits: Limits {
+            step_limit: Some(10_000),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let set = PatternSet::with_options(&["(a|b)*c", "x"], &options).unwrap();
+    assert!(set.backtracked.is_empty());
+    let input = "ab".repeat(10_000);
+    let exceeded = Err(MatchError::StepLimitExceeded { limit: 10_000 });
+    assert_eq!(set.try_is_match_bytes(input.as_bytes()), exceeded);
+    assert!(set.matches_bytes(input.as_bytes()).is_empty());
+    // a match found before the limit is still one
+    assert_eq!(
+        set.try_is_match_bytes(format!("x{}", input).as_bytes()),
+        Ok(true)
+    );
+    assert_eq!(set.try_is_match_bytes(b"abababc"), Ok(true));
+    assert_eq!(set.try_is_match_bytes(b"abab"), Ok(false));
+}
+
+#[test]
+fn test_pattern_set_finds_the_leftmost_match_in_one_pass() {
+    let set = PatternSet::new(&["abcd", "b", r"(\w)\1", "ab"]).unwrap();
+    assert_eq!(set.backtracked, [2]);
+    // like abcd|b|(\w)\1|ab : the first pattern that matches at the leftmost place
+    assert_eq!(set.find_bytes_at(b"xabcd", 0), Some(1..5));
+    assert_eq!(set.find_bytes_at(b"xabc", 0), Some(1..3));
+    assert_eq!(set.find_bytes_at(b"aabc", 0), Some(0..2));
+    assert_eq!(set.find_bytes_at(b"xabcd", 2), Some(2..3));
+    assert_eq!(set.find_bytes_at(b"xyz", 0), None);
+
+    let options = PatternOptions {
+        leftmost_longest: true,
+        ..Default::default()
+    };
+    let set = PatternSet::with_options(&["a", "ab", r"(a)\1b", "abc"], &options).unwrap();
+    assert_eq!(set.find_bytes_at(b"xabcd", 0), Some(1..4));
+    assert_eq!(set.find_bytes_at(b"aab", 0), Some(0..3));
+    assert_eq!(set.find_bytes_at(b"xab", 0), Some(1..3));
+}
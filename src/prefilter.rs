@@ -0,0 +1,171 @@
+use memchr::memmem;
+
+use crate::Pattern::{Group, SubPattern, Token};
+
+// ------------------------------------------------------------------------------//
+//                                   Prefilter                                   //
+// ------------------------------------------------------------------------------//
+
+// a literal every match contains, searched with memchr's substring search before the engine
+// runs: a line without it can't match, and when every match starts with it (a prefix)
+// no match can start before its first occurrence
+#[derive(Debug)]
+pub struct Prefilter {
+    finder: memmem::Finder<'static>,
+    is_prefix: bool,
+}
+
+impl Prefilter {
+    // None when the pattern has no literal worth searching for  (\d+  a|b  (?i)error)
+    pub fn new(sub_patterns: &[SubPattern]) -> Option<Prefilter> {
+        // with several top level alternatives there is no single literal they all contain
+        let [sub_pattern] = sub_patterns else {
+            return None;
+        };
+        let (literal, is_prefix) = required_literal(&sub_pattern.tokens)?;
+        Some(Prefilter {
+            finder: memmem::Finder::new(literal.as_bytes()).into_owned(),
+            is_prefix,
+        })
+    }
+
+    // the first offset a match can start at, None when the literal isn't in the input at all
+    pub fn first_candidate(&self, input: &[u8]) -> Option<usize> {
+        let found = self.finder.find(input)?;
+        Some(if self.is_prefix { found } else { 0 })
+    }
+
+    pub fn literal(&self) -> &str {
+        // the needle was built from a String
+        std::str::from_utf8(self.finder.needle()).unwrap()
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        self.is_prefix
+    }
+}
+
+// the longest run of literal text every match of the tokens contains,
+// and whether the run is where every match starts
+fn required_literal(tokens: &[Token]) -> Option<(String, bool)> {
+    let mut best: Option<(String, bool)> = None;
+    let mut run = String::new();
+    let mut run_is_prefix = true;
+    for token in tokens {
+        match token {
+            // zero-width tokens don't break a run of text  (^foo  foo\b  (?=x)foo)
+            Token::Assertion(_) | Token::LookAround(_) | Token::SetFlags(_) => continue,
+            _ => {}
+        }
+        match exact_text(token) {
+            Some(text) => run.push_str(&text),
+            None => {
+                // a+ or x{3,} still has its first repetitions in every match
+                if let Some(text) = required_repetitions(token) {
+                    run.push_str(&text);
+                }
+                keep_longest(&mut best, &run, run_is_prefix);
+                run.clear();
+                run_is_prefix = false;
+            }
+        }
+    }
+    keep_longest(&mut best, &run, run_is_prefix);
+    best
+}
+
+fn keep_longest(best: &mut Option<(String, bool)>, run: &str, is_prefix: bool) {
+    let longer = best.as_ref().map_or(0, |(literal, _)| literal.len()) < run.len();
+    // a prefix that is as long is better: it also tells where matches start
+    let prefix_as_long = is_prefix && best.as_ref().is_some_and(|(l, _)| l.len() == run.len());
+    if longer || prefix_as_long {
+        *best = Some((run.to_string(), is_prefix));
+    }
+}
+
+// the only text the token can match, None when it can match more than one text
+fn exact_text(token: &Token) -> Option<String> {
+    match token {
+        Token::Literal(c) => Some(c.to_string()),
+        Token::Text(text) => Some(text.clone()),
+        Token::Group(Group { alternatives, .. }) => match alternatives.as_slice() {
+            [alternative] => alternative
+                .tokens
+                .iter()
+                .try_fold(String::new(), |text, token| match token {
+                    Token::Assertion(_) | Token::LookAround(_) | Token::SetFlags(_) => Some(text),
+                    _ => Some(text + &exact_text(token)?),
+                }),
+            _ => None,
+        },
+        Token::Repeat(token, min, Some(max)) if min == max => Some(exact_text(token)?.repeat(*min)),
+        Token::Atomic(token) => exact_text(token),
+        _ => None,
+    }
+}
+
+// the text of the repetitions every match of a greedy or lazy quantifier has
+fn required_repetitions(token: &Token) -> Option<String> {
+    let token = match token {
+        Token::Lazy(token) | Token::Atomic(token) => token,
+        token => token,
+    };
+    let (repeated, min, _) = token.repeat_range()?;
+    Some(exact_text(repeated)?.repeat(min))
+}
+
+#[cfg(test)]
+use crate::Pattern::Pattern;
+
+#[cfg(test)]
+fn literal_of(pattern: &str) -> Option<(String, bool)> {
+    let pattern: Pattern = pattern.parse().unwrap();
+    let prefilter = Prefilter::new(&pattern.sub_patterns)?;
+    Some((prefilter.literal().to_string(), prefilter.is_prefix()))
+}
+
+#[test]
+fn test_prefilter_literals() {
+    let literal = |text: &str, is_prefix| Some((text.to_string(), is_prefix));
+    assert_eq!(
+        literal_of(r"ERROR: \d+ timeout"),
+        literal(" timeout", false)
+    );
+    assert_eq!(literal_of(r"^ERROR: \d+"), literal("ERROR: ", true));
+    assert_eq!(literal_of(r"\d+ms"), literal("ms", false));
+    assert_eq!(literal_of(r"(?:ab){2}c+d"), literal("ababc", true));
+    assert_eq!(literal_of(r"x(y)z\b.*"), literal("xyz", true));
+    assert_eq!(literal_of(r"\w+(cat|dog)"), None);
+    assert_eq!(literal_of(r"cat|dog"), None);
+    assert_eq!(literal_of(r"(?i)error"), None);
+    assert_eq!(literal_of(r"a?b*"), None);
+}
+
+#[test]
+fn test_prefilter_candidates() {
+    let pattern: Pattern = r"^ERROR: \d+".parse().unwrap();
+    let prefilter = Prefilter::new(&pattern.sub_patterns).unwrap();
+    assert_eq!(prefilter.first_candidate(b"warn: ERROR: 5"), Some(6));
+    assert_eq!(prefilter.first_candidate(b"all good"), None);
+
+    let pattern: Pattern = r"\d+ms".parse().unwrap();
+    let prefilter = Prefilter::new(&pattern.sub_patterns).unwrap();
+    assert_eq!(prefilter.first_candidate(b"took 15ms"), Some(0));
+}
+
+#[test]
+fn test_matching_with_a_prefilter() {
+    let pattern: Pattern = r"ERROR: (\d+) timeout".parse().unwrap();
+    assert!(!pattern.matches("ERROR: 12 retry"));
+    let captures = pattern
+        .captures("ERROR: 1 retry, ERROR: 12 timeout")
+        .unwrap();
+    assert_eq!(captures.get(0).unwrap().range(), 16..33);
+    assert_eq!(captures.get(1).unwrap().as_str(), "12");
+
+    // the search starts at the first "ab", and \b still sees the char before it
+    let pattern: Pattern = r"\bab\d".parse().unwrap();
+    assert!(!pattern.matches("xab1"));
+    assert!(pattern.matches("xab1 ab2"));
+    assert_eq!(pattern.find("xab1 ab2").unwrap().range(), 5..8);
+}
@@ -0,0 +1,156 @@
+use std::str::Chars;
+
+// ------------------------------------------------------------------------------//
+//                                POSIX Dialects                                 //
+// ------------------------------------------------------------------------------//
+
+// which regex syntax a pattern is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    // POSIX basic regular expressions, grep -G: ( ) { } | + ? are plain chars and
+    // \( \) \{ \} \| \+ \? are the operators (the last three are GNU extensions)
+    Basic,
+    // POSIX extended regular expressions, grep -E, with the extensions of this engine
+    // (classes like \d, lazy quantifiers, lookarounds, ...)
+    #[default]
+    Extended,
+}
+
+// rewrite a basic regular expression in the extended syntax the parser reads
+//   \(a\|b\)\{2\}  ->  (a|b){2}      a+(b)  ->  a\+\(b\)
+// like in POSIX, * at the start of an expression, ^ anywhere but at its start and $ anywhere
+// but at its end are plain chars, and a \ in a bracket expression is itself
+pub fn basic_to_extended(pattern: &str) -> String {
+    translate(pattern).extended
+}
+
+// the same, with the offset in `pattern` every byte of the translation comes from
+// (and one more for its end), so an error in the translation can point at what was written
+pub fn basic_to_extended_with_offsets(pattern: &str) -> (String, Vec<usize>) {
+    let mut translation = translate(pattern);
+    translation.offsets.push(pattern.len());
+    (translation.extended, translation.offsets)
+}
+
+struct Translation {
+    extended: String,
+    offsets: Vec<usize>,
+}
+
+impl Translation {
+    // `at` is the offset in the basic pattern of what `text` translates
+    fn push(&mut self, text: &str, at: usize) {
+        self.extended.push_str(text);
+        self.offsets.resize(self.extended.len(), at);
+    }
+
+    fn push_char(&mut self, c: char, at: usize) {
+        self.push(c.encode_utf8(&mut [0; 4]), at);
+    }
+}
+
+fn translate(pattern: &str) -> Translation {
+    let mut translation = Translation {
+        extended: String::with_capacity(pattern.len()),
+        offsets: Vec::with_capacity(pattern.len() + 1),
+    };
+    let mut chars = pattern.chars();
+    // at the start of the pattern, of a group or of an alternative
+    let mut at_start = true;
+    loop {
+        let at = pattern.len() - chars.as_str().len();
+        let Some(c) = chars.next() else {
+            break;
+        };
+        let was_at_start = std::mem::replace(&mut at_start, false);
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | '|')) => {
+                    translation.push_char(c, at);
+                    at_start = true;
+                }
+                Some(c @ (')' | '{' | '}' | '+' | '?')) => translation.push_char(c, at),
+                Some(c) => {
+                    translation.push_char('\\', at);
+                    translation.push_char(c, at);
+                }
+                None => translation.push_char('\\', at), // the parser reports it
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                translation.push_char('\\', at);
+                translation.push_char(c, at);
+            }
+            '*' if was_at_start => translation.push("\\*", at),
+            '^' if was_at_start => {
+                translation.push_char('^', at);
+                at_start = true; // ^* is a literal * too
+            }
+            '^' => translation.push("\\^", at),
+            '$' if is_at_end(chars.as_str()) => translation.push_char('$', at),
+            '$' => translation.push("\\$", at),
+            '[' => copy_bracket_expression(pattern, &mut chars, &mut translation),
+            c => translation.push_char(c, at),
+        }
+    }
+    translation
+}
+
+// the end of the pattern, of a group or of an alternative
+fn is_at_end(rest: &str) -> bool {
+    rest.is_empty() || rest.starts_with("\\)") || rest.starts_with("\\|")
+}
+
+// copy [...] with its \ escaped, a ']' first in it or [:name:] doesn't end it
+fn copy_bracket_expression(pattern: &str, chars: &mut Chars, translation: &mut Translation) {
+    let offset = |chars: &Chars| pattern.len() - chars.as_str().len();
+    translation.push_char('[', offset(chars) - 1);
+    let rest = chars.as_str();
+    let mut first = true;
+    loop {
+        let at = offset(chars);
+        let Some(c) = chars.next() else {
+            return;
+        };
+        match c {
+            '^' if first && chars.as_str().len() + 1 == rest.len() => {
+                translation.push_char('^', at);
+                continue; // a ']' right after [^ is still first
+            }
+            ']' if !first => {
+                translation.push_char(']', at);
+                return;
+            }
+            '[' if chars.as_str().starts_with(':') => {
+                let name_end = chars.as_str().find(":]").map_or(0, |end| end + 2);
+                translation.push_char('[', at);
+                translation.push(&chars.as_str()[..name_end], at + 1);
+                *chars = chars.as_str()[name_end..].chars();
+            }
+            '\\' => translation.push("\\\\", at),
+            c => translation.push_char(c, at),
+        }
+        first = false;
+    }
+}
+
+#[test]
+fn test_basic_to_extended() {
+    assert_eq!(basic_to_extended(r"\(a\|b\)\{2,3\}"), "(a|b){2,3}");
+    assert_eq!(basic_to_extended(r"a+(b)?{1}|c"), r"a\+\(b\)\?\{1\}\|c");
+    assert_eq!(basic_to_extended(r"a\+b\?"), "a+b?");
+    assert_eq!(basic_to_extended(r"\(ab\)\1 \w\."), r"(ab)\1 \w\.");
+    assert_eq!(basic_to_extended("*a\\(*b\\|*c\\)"), r"\*a(\*b|\*c)");
+    assert_eq!(basic_to_extended("^*a^b$c$"), r"^\*a\^b\$c$");
+    assert_eq!(basic_to_extended(r"\(^a$\|^b$\)"), "(^a$|^b$)");
+    assert_eq!(
+        basic_to_extended(r"[]\(a]x[^]b][[:digit:]\]"),
+        r"[]\\(a]x[^]b][[:digit:]\\]"
+    );
+}
+
+#[test]
+fn test_basic_to_extended_offsets() {
+    let (extended, offsets) = basic_to_extended_with_offsets(r"a\(b+\)[\x]");
+    assert_eq!(extended, r"a(b\+)[\\x]");
+    assert_eq!(offsets, [0, 1, 3, 4, 4, 5, 7, 8, 8, 9, 10, 11]);
+}
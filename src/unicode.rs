@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::unicode_tables::{
+    case_folding_simple, general_category, perl_decimal, perl_space, perl_word, property_values,
+    script,
+};
+
+// a named set of code points from the tables, what \p{...} matches
+#[derive(Clone, Copy, PartialEq)]
+pub struct Property {
+    pub name: &'static str,
+    pub ranges: &'static [(char, char)],
+}
+
+// the tables are long, printing the name is enough
+impl fmt::Debug for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Property({})", self.name)
+    }
+}
+
+impl Property {
+    pub fn contains(&self, c: char) -> bool {
+        contains(self.ranges, c)
+    }
+}
+
+// the ranges of every table are sorted and don't overlap
+pub fn contains(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+// \w : letters, marks, decimal numbers and connector punctuation
+pub fn is_word_char(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphanumeric() || c == '_';
+    }
+    contains(perl_word::PERL_WORD, c)
+}
+
+// \d : decimal numbers of every script
+pub fn is_digit(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_digit();
+    }
+    contains(perl_decimal::DECIMAL_NUMBER, c)
+}
+
+// \s : the White_Space property
+pub fn is_white_space(c: char) -> bool {
+    contains(perl_space::WHITE_SPACE, c)
+}
+
+// the other characters that are the same letter when case is ignored, by simple case folding
+//   'k' → ['K', 'K' (Kelvin sign)]   'σ' → ['Σ', 'ς']   '1' → []
+pub fn simple_case_folds(c: char) -> &'static [char] {
+    case_folding_simple::CASE_FOLDING_SIMPLE
+        .binary_search_by_key(&c, |&(key, _)| key)
+        .map_or(&[], |index| {
+            case_folding_simple::CASE_FOLDING_SIMPLE[index].1
+        })
+}
+
+// resolve the name inside \p{...}, it can be a general category or a script:
+//   L  Lu  Letter  Uppercase_Letter  Greek  gc=Lu  sc=Grek  Script=Greek
+// names are compared loosely: case, spaces, '_' and '-' don't matter
+pub fn property(name: &str) -> Option<Property> {
+    let (kind, value) = match name.split_once('=') {
+        Some((kind, value)) => (Some(normalize(kind)), value),
+        None => (None, name),
+    };
+    let value = normalize(value);
+    match kind.as_deref() {
+        None => general_category_property(&value).or_else(|| script_property(&value)),
+        Some("gc" | "generalcategory") => general_category_property(&value),
+        Some("sc" | "script") => script_property(&value),
+        Some(_) => None,
+    }
+}
+
+fn general_category_property(value: &str) -> Option<Property> {
+    let name = canonical_value("General_Category", value)?;
+    find_property(general_category::BY_NAME, name)
+}
+
+fn script_property(value: &str) -> Option<Property> {
+    let name = canonical_value("Script", value)?;
+    find_property(script::BY_NAME, name)
+}
+
+// the table name for a normalized alias  ("lu" -> "Uppercase_Letter")
+fn canonical_value(property: &str, value: &str) -> Option<&'static str> {
+    let (_, aliases) = property_values::PROPERTY_VALUES
+        .iter()
+        .find(|(name, _)| *name == property)?;
+    aliases
+        .binary_search_by(|(alias, _)| (*alias).cmp(value))
+        .ok()
+        .map(|index| aliases[index].1)
+}
+
+fn find_property(
+    tables: &'static [(&'static str, &'static [(char, char)])],
+    name: &str,
+) -> Option<Property> {
+    tables
+        .binary_search_by(|(table_name, _)| (*table_name).cmp(name))
+        .ok()
+        .map(|index| Property {
+            name: tables[index].0,
+            ranges: tables[index].1,
+        })
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[test]
+fn test_property_names() {
+    assert_eq!(property("L").unwrap().name, "Letter");
+    assert_eq!(property("Lu").unwrap().name, "Uppercase_Letter");
+    assert_eq!(
+        property("uppercase letter").unwrap().name,
+        "Uppercase_Letter"
+    );
+    assert_eq!(property("Greek").unwrap().name, "Greek");
+    assert_eq!(property("sc=Grek").unwrap().name, "Greek");
+    assert_eq!(
+        property("General_Category=Nd").unwrap().name,
+        "Decimal_Number"
+    );
+    assert!(property("gc=Greek").is_none());
+    assert!(property("Klingon").is_none());
+}
+
+#[test]
+fn test_unicode_classes() {
+    assert!(is_word_char('é') && is_word_char('ж') && is_word_char('7'));
+    assert!(!is_word_char('-'));
+    assert!(is_digit('٣') && !is_digit('x'));
+    assert!(is_white_space('\u{a0}') && is_white_space('\t') && !is_white_space('x'));
+    assert!(property("Greek").unwrap().contains('λ'));
+    assert!(!property("Greek").unwrap().contains('l'));
+}
+
+#[test]
+fn test_simple_case_folds() {
+    assert_eq!(simple_case_folds('a'), &['A']);
+    assert_eq!(simple_case_folds('k'), &['K', '\u{212a}']); // Kelvin sign
+    assert!(simple_case_folds('σ').contains(&'ς'));
+    assert!(simple_case_folds('Ж').contains(&'ж'));
+    assert!(simple_case_folds('1').is_empty());
+}
@@ -0,0 +1,19 @@
+// tables generated from the Unicode Character Database with ucd-generate,
+// see the header of each file for the exact command. The data is under LICENSE-UNICODE.
+#![allow(clippy::redundant_static_lifetimes)]
+
+pub mod case_folding_simple;
+
+pub mod general_category;
+
+#[allow(dead_code)]
+pub mod perl_decimal;
+
+#[allow(dead_code)]
+pub mod perl_space;
+
+pub mod perl_word;
+
+pub mod property_values;
+
+pub mod script;
@@ -0,0 +1,82 @@
+// ------------------------------------------------------------------------------//
+//                                  UTF-8 Input                                  //
+// ------------------------------------------------------------------------------//
+
+// the engines run on the raw bytes of a line and decode a char only when they need one
+// a byte that isn't part of valid UTF-8 (a Latin-1 é, a stray byte) is read as one
+// U+FFFD char of length 1, so the offsets stay those of the bytes
+
+// the first char of `bytes` and its length in bytes, None at the end of the input
+pub fn decode(bytes: &[u8]) -> Option<(char, usize)> {
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(decode_valid(bytes).unwrap_or((char::REPLACEMENT_CHARACTER, 1)))
+}
+
+// the last char of `bytes` and its length in bytes, None at the start of the input
+pub fn decode_last(bytes: &[u8]) -> Option<(char, usize)> {
+    if bytes.is_empty() {
+        return None;
+    }
+    // a char is at most 4 bytes, the shortest valid one ending at the end wins
+    for len in 1..=bytes.len().min(4) {
+        if let Some((c, decoded)) = decode_valid(&bytes[bytes.len() - len..]) {
+            if decoded == len {
+                return Some((c, len));
+            }
+        }
+    }
+    Some((char::REPLACEMENT_CHARACTER, 1))
+}
+
+// the chars of `bytes` with their length in bytes
+pub fn chars(bytes: &[u8]) -> impl Iterator<Item = (char, usize)> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let (c, len) = decode(&bytes[pos..])?;
+        pos += len;
+        Some((c, len))
+    })
+}
+
+// None when `bytes` doesn't start with a valid UTF-8 char
+fn decode_valid(bytes: &[u8]) -> Option<(char, usize)> {
+    let prefix = &bytes[..bytes.len().min(4)];
+    let valid = match std::str::from_utf8(prefix) {
+        Ok(valid) => valid,
+        Err(e) => std::str::from_utf8(&prefix[..e.valid_up_to()]).unwrap(),
+    };
+    valid.chars().next().map(|c| (c, c.len_utf8()))
+}
+
+#[test]
+fn test_decode_valid_and_invalid_bytes() {
+    assert_eq!(decode("é!".as_bytes()), Some(('é', 2)));
+    assert_eq!(decode(b"\xe9t\xe9"), Some(('\u{fffd}', 1)));
+    assert_eq!(decode(b""), None);
+    // a truncated char is one invalid byte at a time
+    let decoded: Vec<(char, usize)> = chars(b"a\xe2\x82").collect();
+    assert_eq!(decoded, [('a', 1), ('\u{fffd}', 1), ('\u{fffd}', 1)]);
+}
+
+#[test]
+fn test_decode_last_agrees_with_decode() {
+    let inputs: [&[u8]; 4] = [
+        "naïve 😀".as_bytes(),
+        b"caf\xe9",
+        b"\xc3\xa9\xa9x\xa9",
+        b"\xf0\x9f\x98",
+    ];
+    for input in inputs {
+        let forward: Vec<(char, usize)> = chars(input).collect();
+        let mut backward = vec![];
+        let mut end = input.len();
+        while let Some((c, len)) = decode_last(&input[..end]) {
+            backward.push((c, len));
+            end -= len;
+        }
+        backward.reverse();
+        assert_eq!(forward, backward, "{:?}", input);
+    }
+}